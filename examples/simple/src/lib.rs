@@ -8,7 +8,9 @@ use minijinja_autoreload::AutoReloader;
 use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
-use tower_etag_cache::{const_lru_provider::ConstLruProvider, EtagCacheLayer};
+use tower_etag_cache::{
+    const_lru_provider::ConstLruProvider, fs_watch_invalidator::FsWatchInvalidator, EtagCacheLayer,
+};
 use tower_http::{
     compression::CompressionLayer,
     services::{ServeDir, ServeFile},
@@ -35,8 +37,12 @@ pub async fn main() {
         .compact()
         .init();
 
-    let etag_cache_layer =
-        EtagCacheLayer::with_default_predicate(ConstLruProvider::<_, _, 255, u8>::init(5));
+    let cache_provider = ConstLruProvider::<_, _, 255, u8>::init(5);
+    // revalidate static files as soon as they are edited
+    let _app_watch_guard = FsWatchInvalidator::new("app")
+        .watch(cache_provider.clone())
+        .unwrap();
+    let etag_cache_layer = EtagCacheLayer::with_default_predicate(cache_provider);
     let app = Router::new()
         .route("/", get(home))
         .route("/index/name", get(name))
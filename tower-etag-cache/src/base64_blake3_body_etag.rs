@@ -1,14 +1,50 @@
-//! Function that calculates a `HeaderValue` as the base64-encoded blake3 hash of a byte slice
+//! Function and [`EtagGenerator`] that calculate an [`EntityTag`] as the base64-encoded blake3 hash of a byte slice
 
 use data_encoding::BASE64;
-use http::HeaderValue;
 
-/// Calculates the etag value as base64 encoded blake3 hash of the body bytes
-pub fn base64_blake3_body_etag(body: impl AsRef<[u8]>) -> HeaderValue {
+use crate::{EntityTag, EtagGenerator, EtagHasher};
+
+/// Calculates the strong etag value as base64 encoded blake3 hash of the body bytes
+pub fn base64_blake3_body_etag(body: impl AsRef<[u8]>) -> EntityTag {
     let mut hasher = blake3::Hasher::new();
     hasher.update(body.as_ref());
+    base64_blake3_hasher_etag(&hasher)
+}
+
+/// Calculates the strong etag value as base64 encoded blake3 hash of all the bytes
+/// fed to `hasher` so far, for when the body is hashed incrementally
+pub fn base64_blake3_hasher_etag(hasher: &blake3::Hasher) -> EntityTag {
     let bytes = hasher.finalize();
     let val = BASE64.encode(bytes.as_bytes());
-    // unwrap-safety: base64 should be always valid ascii
-    HeaderValue::from_str(&val).unwrap()
+    // unwrap-safety: base64 alphabet and padding chars are all valid etagc
+    EntityTag::strong(val).unwrap()
+}
+
+/// [`EtagGenerator`] that calculates strong ETags with [`base64_blake3_body_etag`].
+///
+/// The default generator of the built-in providers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Base64Blake3EtagGenerator;
+
+impl EtagGenerator for Base64Blake3EtagGenerator {
+    /// Boxed since blake3::Hasher is large
+    type Hasher = Box<blake3::Hasher>;
+
+    fn hasher(&self, _parts: &http::response::Parts) -> Self::Hasher {
+        Box::default()
+    }
+
+    fn generate(&self, _parts: &http::response::Parts, body: &[u8]) -> EntityTag {
+        base64_blake3_body_etag(body)
+    }
+}
+
+impl EtagHasher for Box<blake3::Hasher> {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finish(&mut self) -> EntityTag {
+        base64_blake3_hasher_etag(self)
+    }
 }
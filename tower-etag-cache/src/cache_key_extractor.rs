@@ -0,0 +1,43 @@
+use http::{HeaderMap, Uri};
+
+/// Calculates the cache key of a request, which determines the responses that share an ETag.
+///
+/// Built-in [`CacheProvider`](crate::CacheProvider)s are generic over this so that
+/// responses can vary by request properties other than the defaults.
+pub trait CacheKeyExtractor {
+    type Key;
+
+    fn extract<T>(&self, req: &http::Request<T>) -> Self::Key;
+
+    /// Sets the `Vary` response headers listing the request headers that [`Self::extract`] reads
+    fn set_response_headers(&self, headers_mut: &mut HeaderMap);
+
+    /// Whether `key` was extracted from a request targeting `uri`.
+    /// Used to remove all of a URI's entries on invalidation.
+    fn matches_uri(&self, key: &Self::Key, uri: &http::Uri) -> bool;
+
+    /// The URI string saved in `key`, by which built-in providers index their entries
+    /// so that a URI's entries are found without scanning the cache
+    fn key_uri(key: &Self::Key) -> &str;
+
+    /// The URI string [`Self::extract`] saves in the keys of requests targeting `uri`.
+    ///
+    /// Must equal [`Self::key_uri`] for every key that [`Self::matches_uri`] `uri`.
+    fn uri_string(&self, uri: &http::Uri) -> String;
+
+    /// Whether `key` was extracted from a request whose URI path starts with `prefix`.
+    /// Used to remove all entries under a path with [`PurgeRequest::PathPrefix`](crate::PurgeRequest::PathPrefix).
+    fn matches_path_prefix(&self, key: &Self::Key, prefix: &str) -> bool;
+
+    /// Approximate number of heap-allocated bytes owned by `key`, used for byte-budget eviction
+    fn key_heap_size(key: &Self::Key) -> usize;
+}
+
+/// Whether the path of `uri_string`, a URI saved in a cache key, starts with `prefix`.
+///
+/// `false` if `uri_string` cannot be parsed as a URI.
+pub fn uri_path_starts_with(uri_string: &str, prefix: &str) -> bool {
+    uri_string
+        .parse::<Uri>()
+        .is_ok_and(|uri| uri.path().starts_with(prefix))
+}
@@ -1,6 +1,12 @@
-use http::HeaderMap;
+use bytes::Bytes;
+use http::{
+    header::{AUTHORIZATION, COOKIE},
+    HeaderMap, HeaderName,
+};
 use tower_service::Service;
 
+use crate::EntityTag;
+
 /// Struct returned by a [`CacheProvider`]'s first cache-lookup `Service`
 #[derive(Debug)]
 pub struct CacheGetResponse<ReqBody, Key> {
@@ -12,12 +18,118 @@ pub struct CacheGetResponse<ReqBody, Key> {
 /// Result of the cache-lookup `Service`
 ///
 /// Either
-/// - calculated cache key if entry not in cache, so that the key can be used to put later on
-/// - HTTP response headers to send along with the HTTP 304 response if entry in cache
+/// - calculated cache key if entry not in cache or the request's preconditions passed,
+///   so that the key can be used to put later on
+/// - the cached entity tag and HTTP response headers to send along with the HTTP 304 response
+///   if entry in cache and the request's `If-None-Match` matches it.
+///   The `ETag` header of the 304 response is set from the entity tag.
+/// - HTTP response headers to send along with the HTTP 412 response
+///   if entry in cache and the request's `If-Match` or `If-Unmodified-Since` precondition failed
+/// - the cached entity tag and response to send as a HTTP 200 response without running the inner service
+///   if entry in cache with a saved response and the request's preconditions passed.
+///   The `ETag` header of the 200 response is set from the entity tag.
 #[derive(Debug, Clone)]
 pub enum CacheGetResponseResult<Key> {
     Miss(Key),
-    Hit(HeaderMap),
+    Hit(EntityTag, HeaderMap),
+    PreconditionFailed(HeaderMap),
+    Full(EntityTag, CachedResponse),
+}
+
+/// Body and HTTP response headers of a response saved by a [`CacheProvider`]
+/// so that later requests can be served from the cache
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CachedResponse {
+    /// Includes `Content-Length`, excludes `ETag`
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// Request to a [`CacheProvider`]'s cache-invalidation `Service`.
+///
+/// Sent by [`EtagCache`](crate::EtagCache) when a request with an unsafe method returns a 2XX response.
+/// The provider should remove all cache entries for these URIs, regardless of varying request headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheInvalidateRequest {
+    /// The request's target URI, followed by the response's `Location` and `Content-Location` URIs
+    /// if they have the same origin.
+    /// These are in the same form as the request's target URI,
+    /// which is typically origin-form, i.e. only path and query.
+    pub uris: Vec<http::Uri>,
+}
+
+/// `http::Response` extension that signals to a [`CacheProvider`]'s cache-put `Service`
+/// that the response is to a `HEAD` request.
+///
+/// Such responses have no body to calculate an ETag from, so the provider must not save one.
+/// It should instead set the `ETag` and cache headers of the entry already saved for the key, if any.
+///
+/// Inserted by [`EtagCache`](crate::EtagCache) unless
+/// [`EtagCache::head_fetches_get`](crate::EtagCache::head_fetches_get) is enabled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct HeadResponse;
+
+/// `http::Response` extension that signals to a [`CacheProvider`]'s cache-put `Service`
+/// that the response body is not sent to the client,
+/// because the response is to a `HEAD` request whose `GET` representation was fetched.
+///
+/// Servers such as hyper never poll the bodies of `HEAD` responses,
+/// so a provider that hashes bodies as they are streamed must instead read the body to completion
+/// before returning the response, which should then carry the calculated ETag.
+///
+/// Inserted by [`EtagCache`](crate::EtagCache) if
+/// [`EtagCache::head_fetches_get`](crate::EtagCache::head_fetches_get) is enabled.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct DiscardedBody;
+
+/// `http::Request` extension that signals to a [`CacheProvider`]'s cache-lookup `Service`
+/// that the request has an unsafe method and is only looked up to evaluate its preconditions,
+/// see [`EtagCache::enforce_preconditions`](crate::EtagCache::enforce_preconditions).
+///
+/// The provider should evaluate them against all cache entries for the request's target URI,
+/// regardless of varying request headers, with [`Validators::evaluate_any`](crate::Validators::evaluate_any).
+/// If there are none, it should return [`CacheGetResponseResult::Miss`].
+///
+/// Inserted by [`EtagCache`](crate::EtagCache) and removed once the lookup completes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct PreconditionCheck;
+
+/// `http::Response` extension that signals to a [`CacheProvider`]'s cache-put `Service`
+/// which credentials the request carried.
+///
+/// Responses to requests with credentials must not be served to other requests from the cache
+/// unless the cache key varies on them, see
+/// [RFC 9111 section 3.5](https://www.rfc-editor.org/rfc/rfc9111#section-3.5),
+/// so a provider that saves full responses should not save such responses.
+///
+/// Inserted by [`EtagCache`](crate::EtagCache) if the request has an `Authorization` or `Cookie` header.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct RequestCredentials {
+    pub authorization: bool,
+    pub cookie: bool,
+}
+
+impl RequestCredentials {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            authorization: headers.contains_key(AUTHORIZATION),
+            cookie: headers.contains_key(COOKIE),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.authorization && !self.cookie
+    }
+
+    /// Names of the request headers that carried the credentials
+    pub fn header_names(&self) -> impl Iterator<Item = HeaderName> {
+        [
+            self.authorization.then_some(AUTHORIZATION),
+            self.cookie.then_some(COOKIE),
+        ]
+        .into_iter()
+        .flatten()
+    }
 }
 
 /// Typical type args for use in axum 0.7:
@@ -29,6 +141,7 @@ pub enum CacheGetResponseResult<Key> {
 pub trait CacheProvider<ReqBody, ResBody>:
     Service<http::Request<ReqBody>, Response = CacheGetResponse<ReqBody, Self::Key>> // Get
     + Service<(Self::Key, http::Response<ResBody>), Response = http::Response<Self::TResBody>> // Put
+    + Service<CacheInvalidateRequest, Response = ()> // Invalidate
 {
     /// The cache key type
     type Key;
@@ -0,0 +1,346 @@
+//! Cache key derived from uri and varying by a runtime-configurable list of request headers
+
+use http::{
+    header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, VARY},
+    HeaderMap, HeaderName, HeaderValue,
+};
+use std::{mem::size_of, sync::Arc};
+
+use crate::{uri_path_starts_with, CacheKeyExtractor};
+
+mod negotiation;
+mod origin;
+mod uri;
+
+pub use origin::SchemeSource;
+pub use uri::*;
+
+use negotiation::{bucket_entries, format_qvalue, is_negotiation_header, parse_weighted_entries};
+
+/// Cache key derived from uri, and varying by the request headers of the
+/// [`ConfigurableEtagCacheKeyExtractor`] that calculated it.
+///
+/// `header_values[i]` holds the sorted values of the extractor's `i`th header name,
+/// or a single canonical value if the header is normalized.
+///
+/// `scheme` and `host` are `None` unless the extractor is configured to include them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConfigurableEtagCacheKey {
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+    pub uri_string: String,
+    pub header_values: Vec<Vec<HeaderValue>>,
+}
+
+/// [`CacheKeyExtractor`] for [`ConfigurableEtagCacheKey`]s that vary by a list of request header names.
+///
+/// Defaults to the same headers as [`SimpleEtagCacheKey`](crate::simple_etag_cache_key::SimpleEtagCacheKey):
+/// `Accept`, `Accept-Encoding` and `Accept-Language`.
+///
+/// The raw values of `Accept`, `Accept-Encoding` and `Accept-Language` can be canonicalized
+/// so that requests that only differ in formatting share a key,
+/// see [`Self::normalize_negotiation`] and [`Self::bucket`].
+/// URIs can be canonicalized with [`Self::normalize_uri`].
+///
+/// Servers that serve multiple hosts from the same cache should use [`Self::include_host`]
+/// so that responses of different hosts never share ETags.
+///
+/// Cheap to clone.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConfigurableEtagCacheKeyExtractor {
+    vary: Arc<[HeaderName]>,
+    normalize: bool,
+    /// Supported values per negotiation header
+    buckets: Arc<[(HeaderName, Box<[String]>)]>,
+    uri_normalizer: Arc<UriNormalizer>,
+    include_host: bool,
+    scheme_source: SchemeSource,
+}
+
+impl ConfigurableEtagCacheKeyExtractor {
+    /// Varies by `headers`, ignoring duplicates
+    pub fn new(headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        let mut vary: Vec<HeaderName> = Vec::new();
+        for name in headers {
+            if !vary.contains(&name) {
+                vary.push(name);
+            }
+        }
+        Self {
+            vary: vary.into(),
+            normalize: false,
+            buckets: Arc::new([]),
+            uri_normalizer: Arc::default(),
+            include_host: false,
+            scheme_source: SchemeSource::None,
+        }
+    }
+
+    /// Also varies by `header`
+    pub fn with_header(self, header: HeaderName) -> Self {
+        Self {
+            vary: Self::new(self.vary.iter().cloned().chain([header])).vary,
+            ..self
+        }
+    }
+
+    /// No longer varies by `header`
+    pub fn without_header(self, header: &HeaderName) -> Self {
+        Self {
+            vary: Self::new(self.vary.iter().filter(|n| *n != header).cloned()).vary,
+            ..self
+        }
+    }
+
+    /// Canonicalizes the values of `Accept`, `Accept-Encoding` and `Accept-Language`:
+    /// lowercases them, drops entries with `q=0`, and sorts the rest by descending q-value then name.
+    ///
+    /// For example, `gzip;q=1.0, BR` and `br,gzip` result in the same key.
+    pub fn normalize_negotiation(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Normalizes `header`, one of `Accept`, `Accept-Encoding` or `Accept-Language`,
+    /// and replaces each of its entries with the `supported` values it matches, dropping unmatched entries.
+    ///
+    /// Entries match a supported value if they are equal, ignoring case, or
+    /// - `*` or `*/*`
+    /// - a media range such as `text/*` whose type matches
+    /// - a language range whose prefix matches, so `en` matches `en-us` and `en-us` matches `en`
+    ///
+    /// For example, with supported encodings `br` and `gzip`, `gzip, deflate` and `gzip` result in the same key.
+    pub fn bucket<S: Into<String>>(
+        self,
+        header: HeaderName,
+        supported: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let supported = supported
+            .into_iter()
+            .map(|s| s.into().to_ascii_lowercase())
+            .collect();
+        let mut buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .filter(|(n, _)| *n != header)
+            .cloned()
+            .collect();
+        buckets.push((header, supported));
+        Self {
+            buckets: buckets.into(),
+            ..self
+        }
+    }
+
+    /// Canonicalizes request URIs with `uri_normalizer`.
+    ///
+    /// The URIs of [`CacheInvalidateRequest`](crate::CacheInvalidateRequest)s are normalized the same way.
+    pub fn normalize_uri(self, uri_normalizer: UriNormalizer) -> Self {
+        Self {
+            uri_normalizer: Arc::new(uri_normalizer),
+            ..self
+        }
+    }
+
+    /// Includes the request URI's authority, or the `Host` header if absent, in keys.
+    ///
+    /// Invalidation URIs without an authority, which is typical, invalidate the entries of all hosts.
+    pub fn include_host(mut self, include_host: bool) -> Self {
+        self.include_host = include_host;
+        self
+    }
+
+    /// Includes the scheme from `scheme_source` in keys
+    pub fn include_scheme(mut self, scheme_source: SchemeSource) -> Self {
+        self.scheme_source = scheme_source;
+        self
+    }
+
+    /// The request header names keys vary by
+    pub fn vary(&self) -> &[HeaderName] {
+        &self.vary
+    }
+}
+
+impl Default for ConfigurableEtagCacheKeyExtractor {
+    fn default() -> Self {
+        Self::new([ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE])
+    }
+}
+
+impl CacheKeyExtractor for ConfigurableEtagCacheKeyExtractor {
+    type Key = ConfigurableEtagCacheKey;
+
+    fn extract<T>(&self, req: &http::Request<T>) -> Self::Key {
+        let headers = req.headers();
+        ConfigurableEtagCacheKey {
+            scheme: self.scheme_source.scheme(req),
+            host: self.include_host.then(|| origin::host(req)).flatten(),
+            uri_string: self.uri_normalizer.normalize(req.uri()),
+            header_values: self
+                .vary
+                .iter()
+                .map(|name| self.calc_header_key_component(headers, name))
+                .collect(),
+        }
+    }
+
+    /// Appends a single `Vary` header listing the configured header names
+    /// that are not already listed by the response's existing `Vary` headers.
+    ///
+    /// Does nothing if the response already has `Vary: *`.
+    fn set_response_headers(&self, headers_mut: &mut HeaderMap) {
+        let existing: Vec<&str> = headers_mut
+            .get_all(VARY)
+            .iter()
+            .filter_map(|hv| hv.to_str().ok())
+            .flat_map(|s| s.split(','))
+            .map(str::trim)
+            .collect();
+        if existing.contains(&"*") {
+            return;
+        }
+        let missing: Vec<&str> = self
+            .vary
+            .iter()
+            .map(HeaderName::as_str)
+            .filter(|name| !existing.iter().any(|e| e.eq_ignore_ascii_case(name)))
+            .collect();
+        if missing.is_empty() {
+            return;
+        }
+        // unwrap-safety: header names are all valid header value chars
+        let val = HeaderValue::from_str(&missing.join(", ")).unwrap();
+        headers_mut.append(VARY, val);
+    }
+
+    /// Also compares hosts if both `key` and `uri` have one
+    fn matches_uri(&self, key: &Self::Key, uri: &http::Uri) -> bool {
+        if let (Some(key_host), Some(authority)) = (&key.host, uri.authority()) {
+            if !key_host.eq_ignore_ascii_case(authority.as_str()) {
+                return false;
+            }
+        }
+        match *self.uri_normalizer == UriNormalizer::default() {
+            // skip allocating the normalized string
+            true => uri == key.uri_string.as_str(),
+            false => self.uri_normalizer.normalize(uri) == key.uri_string,
+        }
+    }
+
+    /// The normalized URI, without the host
+    fn key_uri(key: &Self::Key) -> &str {
+        &key.uri_string
+    }
+
+    fn uri_string(&self, uri: &http::Uri) -> String {
+        self.uri_normalizer.normalize(uri)
+    }
+
+    /// `prefix` is compared against the normalized URI's path
+    fn matches_path_prefix(&self, key: &Self::Key, prefix: &str) -> bool {
+        uri_path_starts_with(&key.uri_string, prefix)
+    }
+
+    fn key_heap_size(key: &Self::Key) -> usize {
+        let header_values_size: usize = key
+            .header_values
+            .iter()
+            .map(|hvs| {
+                size_of::<Vec<HeaderValue>>()
+                    + hvs
+                        .iter()
+                        .map(|hv| size_of::<HeaderValue>() + hv.len())
+                        .sum::<usize>()
+            })
+            .sum();
+        let origin_size: usize = [&key.scheme, &key.host]
+            .into_iter()
+            .flatten()
+            .map(String::len)
+            .sum();
+        origin_size + key.uri_string.len() + header_values_size
+    }
+}
+
+impl ConfigurableEtagCacheKeyExtractor {
+    fn calc_header_key_component(
+        &self,
+        headers: &HeaderMap,
+        name: &HeaderName,
+    ) -> Vec<HeaderValue> {
+        let bucket = self
+            .buckets
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, supported)| &**supported);
+        if !is_negotiation_header(name) || (!self.normalize && bucket.is_none()) {
+            let mut res: Vec<_> = headers.get_all(name).iter().cloned().collect();
+            res.sort_unstable();
+            return res;
+        }
+        let mut entries = parse_weighted_entries(headers, name);
+        if let Some(supported) = bucket {
+            entries = bucket_entries(&entries, supported);
+        }
+        // keep the highest q-value of repeated values
+        entries.sort_unstable_by(|(a, qa), (b, qb)| a.cmp(b).then_with(|| qb.cmp(qa)));
+        entries.dedup_by(|(a, _), (b, _)| a == b);
+        // descending q-value, then name
+        entries.sort_unstable_by(|(a, qa), (b, qb)| qb.cmp(qa).then_with(|| a.cmp(b)));
+        if entries.is_empty() {
+            return Vec::new();
+        }
+        let canonical = entries
+            .iter()
+            .map(|(v, q)| match q {
+                1000 => v.clone(),
+                q => format!("{v};q={}", format_qvalue(*q)),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        // unwrap-safety: built from visible ascii chars of existing header values
+        vec![HeaderValue::from_str(&canonical).unwrap()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accept_key(
+        extractor: &ConfigurableEtagCacheKeyExtractor,
+        values: &[&str],
+    ) -> Vec<HeaderValue> {
+        let mut req = http::Request::builder().uri("/");
+        for v in values {
+            req = req.header(ACCEPT_ENCODING, *v);
+        }
+        let key = extractor.extract(&req.body(()).unwrap());
+        key.header_values[1].clone()
+    }
+
+    #[test]
+    fn normalized_negotiation_is_canonical() {
+        let extractor = ConfigurableEtagCacheKeyExtractor::default().normalize_negotiation(true);
+        let canonical = accept_key(
+            &extractor,
+            &["br;q=0.5, GZIP", "deflate;q=0.50, gzip;q=0.2"],
+        );
+        assert_eq!(canonical, ["gzip, br;q=0.5, deflate;q=0.5"]);
+        assert_eq!(
+            accept_key(&extractor, &["deflate;q=0.5,br;q=0.5,gzip"]),
+            canonical
+        );
+        assert!(accept_key(&extractor, &["gzip;q=0, br;q=oops"]).is_empty());
+    }
+
+    #[test]
+    fn raw_values_are_only_sorted() {
+        let extractor = ConfigurableEtagCacheKeyExtractor::default();
+        assert_eq!(
+            accept_key(&extractor, &["gzip", "br;q=0"]),
+            ["br;q=0", "gzip"]
+        );
+    }
+}
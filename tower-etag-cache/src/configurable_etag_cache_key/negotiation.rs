@@ -0,0 +1,197 @@
+//! Parsing and canonicalization of content negotiation header values
+
+use http::{
+    header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE},
+    HeaderMap, HeaderName,
+};
+
+pub(super) fn is_negotiation_header(name: &HeaderName) -> bool {
+    name == ACCEPT || name == ACCEPT_ENCODING || name == ACCEPT_LANGUAGE
+}
+
+/// Parses comma-separated entries into lowercased values, with their other parameters
+/// but without whitespace, and q-values in thousandths. Drops entries with `q=0`.
+pub(super) fn parse_weighted_entries(headers: &HeaderMap, name: &HeaderName) -> Vec<(String, u16)> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|s| s.split(','))
+        .filter_map(|entry| {
+            let mut params = entry.split(';').map(str::trim);
+            let value = params.next().filter(|v| !v.is_empty())?;
+            let mut value = value.to_ascii_lowercase();
+            let mut q = 1000;
+            for param in params.filter(|p| !p.is_empty()) {
+                match param.split_once('=') {
+                    Some((k, v)) if k.trim().eq_ignore_ascii_case("q") => {
+                        q = parse_qvalue(v.trim())?;
+                    }
+                    _ => {
+                        value.push(';');
+                        value.extend(param.chars().filter(|c| !c.is_ascii_whitespace()));
+                    }
+                }
+            }
+            value.make_ascii_lowercase();
+            (q > 0).then_some((value, q))
+        })
+        .collect()
+}
+
+/// Replaces each entry with the supported values it matches, keeping the highest q-value per supported value
+pub(super) fn bucket_entries(
+    entries: &[(String, u16)],
+    supported: &[String],
+) -> Vec<(String, u16)> {
+    supported
+        .iter()
+        .filter_map(|s| {
+            entries
+                .iter()
+                .filter(|(v, _)| entry_matches(v, s))
+                .map(|(_, q)| *q)
+                .max()
+                .map(|q| (s.clone(), q))
+        })
+        .collect()
+}
+
+fn entry_matches(entry: &str, supported: &str) -> bool {
+    // ignore parameters such as media type level
+    let entry = entry.split(';').next().unwrap_or_default();
+    if entry == supported || entry == "*" || entry == "*/*" {
+        return true;
+    }
+    if let Some(ty) = entry.strip_suffix("/*") {
+        return supported.split('/').next() == Some(ty);
+    }
+    let is_prefix =
+        |prefix: &str, tag: &str| tag.strip_prefix(prefix).is_some_and(|r| r.starts_with('-'));
+    is_prefix(entry, supported) || is_prefix(supported, entry)
+}
+
+/// Parses a qvalue as thousandths, returning `None` if invalid
+fn parse_qvalue(s: &str) -> Option<u16> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let frac_thousandths = format!("{frac:0<3}").parse::<u16>().ok()?;
+    match int {
+        "0" => Some(frac_thousandths),
+        "1" if frac_thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+pub(super) fn format_qvalue(q: u16) -> String {
+    let s = format!("0.{q:03}");
+    s.trim_end_matches('0').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn entries(values: &[&'static str]) -> Vec<(String, u16)> {
+        let mut headers = HeaderMap::new();
+        for v in values {
+            headers.append(ACCEPT, HeaderValue::from_static(v));
+        }
+        parse_weighted_entries(&headers, &ACCEPT)
+    }
+
+    fn owned(expected: &[(&str, u16)]) -> Vec<(String, u16)> {
+        expected.iter().map(|(v, q)| (v.to_string(), *q)).collect()
+    }
+
+    #[test]
+    fn parse_entries() {
+        assert_eq!(
+            entries(&["Text/HTML ;Level = 1; q=0.5, application/json", "*/*;Q=0.1"]),
+            owned(&[
+                ("text/html;level=1", 500),
+                ("application/json", 1000),
+                ("*/*", 100)
+            ])
+        );
+        assert_eq!(entries(&[" , ;q=0.5,, gzip"]), owned(&[("gzip", 1000)]));
+        assert_eq!(entries(&[]), owned(&[]));
+    }
+
+    #[test]
+    fn parse_entries_drops_q_zero() {
+        assert_eq!(
+            entries(&["gzip;q=0, br;q=0.000, identity;q=0.001"]),
+            owned(&[("identity", 1)])
+        );
+    }
+
+    #[test]
+    fn parse_entries_drops_invalid_qvalues() {
+        assert_eq!(
+            entries(&["a;q=2, b;q=1.5, c;q=0.5555, d;q=x, e;q=, f;q=-0.5, g;q=.5, h;q=0.25"]),
+            owned(&[("h", 250)])
+        );
+    }
+
+    #[test]
+    fn qvalues() {
+        for (s, q) in [
+            ("0", 0),
+            ("0.", 0),
+            ("0.5", 500),
+            ("0.05", 50),
+            ("0.123", 123),
+            ("1", 1000),
+            ("1.", 1000),
+            ("1.000", 1000),
+        ] {
+            assert_eq!(parse_qvalue(s), Some(q), "{s}");
+        }
+        for s in [
+            "", "1.001", "2", "0.1234", "00.5", "+0.5", ".5", "0.5a", "0,5",
+        ] {
+            assert_eq!(parse_qvalue(s), None, "{s}");
+        }
+    }
+
+    #[test]
+    fn format_qvalues() {
+        assert_eq!(format_qvalue(500), "0.5");
+        assert_eq!(format_qvalue(50), "0.05");
+        assert_eq!(format_qvalue(123), "0.123");
+        assert_eq!(format_qvalue(1), "0.001");
+        for q in 1..1000 {
+            assert_eq!(parse_qvalue(&format_qvalue(q)), Some(q));
+        }
+    }
+
+    #[test]
+    fn bucket_keeps_highest_qvalue() {
+        let supported = ["text/html".to_owned(), "application/json".to_owned()];
+        assert_eq!(
+            bucket_entries(
+                &owned(&[("text/*", 300), ("text/html;level=1", 800), ("*/*", 100)]),
+                &supported
+            ),
+            owned(&[("text/html", 800), ("application/json", 100)])
+        );
+        assert_eq!(
+            bucket_entries(&owned(&[("image/png", 1000)]), &supported),
+            owned(&[])
+        );
+    }
+
+    #[test]
+    fn language_prefixes_match() {
+        assert!(entry_matches("en", "en-us"));
+        assert!(entry_matches("en-us", "en"));
+        assert!(entry_matches("*", "de"));
+        assert!(!entry_matches("en", "eng"));
+        assert!(!entry_matches("en-gb", "en-us"));
+    }
+}
@@ -0,0 +1,68 @@
+use http::{header::FORWARDED, HeaderMap, HeaderName};
+
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// Where the scheme stored in a [`ConfigurableEtagCacheKey`](super::ConfigurableEtagCacheKey) comes from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SchemeSource {
+    /// Keys do not include the scheme
+    #[default]
+    None,
+
+    /// The request URI's scheme, which is usually only present for HTTP/2 requests
+    Uri,
+
+    /// The `proto` of the first `Forwarded` element, else the first `X-Forwarded-Proto` value,
+    /// else the request URI's scheme.
+    ///
+    /// Only use this behind a reverse proxy that overwrites these headers,
+    /// since clients can otherwise set them to anything.
+    TrustedForwarded,
+}
+
+impl SchemeSource {
+    pub(super) fn scheme<T>(&self, req: &http::Request<T>) -> Option<String> {
+        let from_uri = || req.uri().scheme_str().map(str::to_ascii_lowercase);
+        match self {
+            Self::None => None,
+            Self::Uri => from_uri(),
+            Self::TrustedForwarded => forwarded_proto(req.headers())
+                .or_else(|| x_forwarded_proto(req.headers()))
+                .or_else(from_uri),
+        }
+    }
+}
+
+/// The request URI's authority, else the `Host` header
+pub(super) fn host<T>(req: &http::Request<T>) -> Option<String> {
+    req.uri()
+        .authority()
+        .map(|a| a.as_str())
+        .or_else(|| {
+            req.headers()
+                .get(http::header::HOST)
+                .and_then(|hv| hv.to_str().ok())
+        })
+        .map(str::to_ascii_lowercase)
+}
+
+fn forwarded_proto(headers: &HeaderMap) -> Option<String> {
+    let first_element = headers.get(FORWARDED)?.to_str().ok()?.split(',').next()?;
+    first_element.split(';').find_map(|pair| {
+        let (name, value) = pair.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("proto")
+            .then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+    })
+}
+
+fn x_forwarded_proto(headers: &HeaderMap) -> Option<String> {
+    let first = headers
+        .get(X_FORWARDED_PROTO)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()?
+        .trim();
+    (!first.is_empty()).then(|| first.to_ascii_lowercase())
+}
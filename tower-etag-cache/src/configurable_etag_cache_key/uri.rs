@@ -0,0 +1,308 @@
+use std::fmt::Write;
+
+/// Canonicalizes request URIs before they are stored in a [`ConfigurableEtagCacheKey`](super::ConfigurableEtagCacheKey)
+/// so that URIs that address the same resource share a key.
+///
+/// The default leaves URIs unchanged. Otherwise, the scheme and authority of absolute URIs are also lowercased.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct UriNormalizer {
+    /// Sorts query parameters by name, keeping the order of repeated names
+    pub sort_query: bool,
+
+    /// Which query parameters are kept, by name
+    pub query_filter: QueryFilter,
+
+    /// Uppercases the hex digits of percent-encoded octets and
+    /// decodes those of unreserved characters, as per
+    /// [RFC 3986 section 6.2.2.2](https://www.rfc-editor.org/rfc/rfc3986#section-6.2.2.2)
+    pub normalize_percent_encoding: bool,
+
+    /// Lowercases the path and query parameter names, and uppercases the hex digits of their percent-encoded octets
+    pub case_insensitive: bool,
+
+    pub trailing_slash: TrailingSlash,
+}
+
+/// Filters query parameters by name.
+///
+/// Both the configured names and those of the request are compared after the
+/// [`UriNormalizer`]'s case folding and percent-encoding normalization, if enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum QueryFilter {
+    /// Keeps all parameters
+    #[default]
+    All,
+
+    /// Only keeps parameters with these names
+    Allow(Vec<String>),
+
+    /// Removes parameters with these names, e.g. `utm_source`
+    Deny(Vec<String>),
+}
+
+/// Handling of a trailing `/` at the end of the path. The root path `/` is never changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TrailingSlash {
+    #[default]
+    Keep,
+
+    /// Removes trailing slashes, so `/a/` becomes `/a`
+    Trim,
+
+    /// Appends a slash if missing, so `/a` becomes `/a/`
+    Add,
+}
+
+impl UriNormalizer {
+    /// Returns the canonical string form of `uri`
+    pub fn normalize(&self, uri: &http::Uri) -> String {
+        if *self == Self::default() {
+            return uri.to_string();
+        }
+        let mut res = String::new();
+        // scheme and host are always case-insensitive
+        if let (Some(scheme), Some(authority)) = (uri.scheme_str(), uri.authority()) {
+            // write to String is infallible
+            let _ = write!(
+                res,
+                "{}://{}",
+                scheme.to_ascii_lowercase(),
+                authority.as_str().to_ascii_lowercase()
+            );
+        }
+        res.push_str(&self.normalize_path(uri.path()));
+        if let Some(query) = uri.query() {
+            let query = self.normalize_query(query);
+            if !query.is_empty() {
+                res.push('?');
+                res.push_str(&query);
+            }
+        }
+        res
+    }
+
+    fn normalize_path(&self, path: &str) -> String {
+        let mut path = self.normalize_component(path);
+        match self.trailing_slash {
+            TrailingSlash::Keep => (),
+            TrailingSlash::Trim => {
+                let trimmed_len = path.trim_end_matches('/').len().max(1);
+                path.truncate(trimmed_len);
+            }
+            TrailingSlash::Add => {
+                if !path.ends_with('/') {
+                    path.push('/');
+                }
+            }
+        }
+        path
+    }
+
+    fn normalize_query(&self, query: &str) -> String {
+        let mut params: Vec<(String, Option<String>)> = query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| match p.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (p, None),
+            })
+            .map(|(name, value)| {
+                let value = value.map(|v| match self.normalize_percent_encoding {
+                    true => normalize_percent_encoding(v),
+                    false => v.to_owned(),
+                });
+                (self.normalize_component(name), value)
+            })
+            .filter(|(name, _)| self.keeps_param(name))
+            .collect();
+        if self.sort_query {
+            params.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        let mut res = String::with_capacity(query.len());
+        for (i, (name, value)) in params.iter().enumerate() {
+            if i > 0 {
+                res.push('&');
+            }
+            res.push_str(name);
+            if let Some(value) = value {
+                res.push('=');
+                res.push_str(value);
+            }
+        }
+        res
+    }
+
+    /// `name` is already normalized, configured names are normalized the same way before comparing
+    fn keeps_param(&self, name: &str) -> bool {
+        let listed = |names: &[String]| names.iter().any(|n| self.normalize_component(n) == name);
+        match &self.query_filter {
+            QueryFilter::All => true,
+            QueryFilter::Allow(names) => listed(names),
+            QueryFilter::Deny(names) => !listed(names),
+        }
+    }
+
+    /// Percent-encoding normalization, then case folding so that decoded letters are folded too
+    fn normalize_component(&self, s: &str) -> String {
+        let s = match self.normalize_percent_encoding {
+            true => normalize_percent_encoding(s),
+            false => s.to_owned(),
+        };
+        match self.case_insensitive {
+            true => fold_case(&s),
+            false => s,
+        }
+    }
+}
+
+/// Lowercases everything but the hex digits of percent-encoded octets, which are uppercased
+fn fold_case(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut hex_digits = 0;
+    for (i, c) in s.char_indices() {
+        if hex_digits > 0 {
+            res.push(c.to_ascii_uppercase());
+            hex_digits -= 1;
+            continue;
+        }
+        let is_octet = c == '%'
+            && s.get(i + 1..i + 3)
+                .is_some_and(|h| h.bytes().all(|b| b.is_ascii_hexdigit()));
+        if is_octet {
+            hex_digits = 2;
+        }
+        res.push(c.to_ascii_lowercase());
+    }
+    res
+}
+
+fn normalize_percent_encoding(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('%') {
+        res.push_str(&rest[..i]);
+        let hex = rest
+            .get(i + 1..i + 3)
+            .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()));
+        match hex {
+            Some(hex) => {
+                // unwrap-safety: 2 hex digits always fit in a u8
+                let octet = u8::from_str_radix(hex, 16).unwrap();
+                if octet.is_ascii_alphanumeric() || b"-._~".contains(&octet) {
+                    res.push(octet.into());
+                } else {
+                    res.push('%');
+                    res.push_str(&hex.to_ascii_uppercase());
+                }
+                rest = &rest[i + 3..];
+            }
+            None => {
+                res.push('%');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(normalizer: &UriNormalizer, uri: &'static str) -> String {
+        normalizer.normalize(&http::Uri::from_static(uri))
+    }
+
+    #[test]
+    fn default_keeps_uri() {
+        let uri = "HTTP://Example.com/A/?b=1&a=%7e";
+        assert_eq!(
+            normalize(&UriNormalizer::default(), uri),
+            http::Uri::from_static(uri).to_string()
+        );
+    }
+
+    #[test]
+    fn sort_query_is_stable() {
+        let normalizer = UriNormalizer {
+            sort_query: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&normalizer, "HTTP://Example.com/p?b=2&a=1&b=1&&c"),
+            "http://example.com/p?a=1&b=2&b=1&c"
+        );
+    }
+
+    #[test]
+    fn percent_encoding() {
+        let normalizer = UriNormalizer {
+            normalize_percent_encoding: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&normalizer, "/%7e%2f%4A%zz%4?%61=%2a"),
+            "/~%2FJ%zz%4?a=%2A"
+        );
+    }
+
+    #[test]
+    fn case_folding_after_decoding() {
+        let normalizer = UriNormalizer {
+            normalize_percent_encoding: true,
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&normalizer, "/A%4A%2f?%4B=V"), "/aj%2F?k=V");
+        let fold_only = UriNormalizer {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&fold_only, "/A%4a%2f?B=V"), "/a%4A%2F?b=V");
+    }
+
+    #[test]
+    fn trailing_slash() {
+        let trim = UriNormalizer {
+            trailing_slash: TrailingSlash::Trim,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&trim, "/a//?x"), "/a?x");
+        assert_eq!(normalize(&trim, "/"), "/");
+        let add = UriNormalizer {
+            trailing_slash: TrailingSlash::Add,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&add, "/a"), "/a/");
+        assert_eq!(normalize(&add, "/a/"), "/a/");
+    }
+
+    #[test]
+    fn query_filter_names_are_normalized() {
+        let allow = UriNormalizer {
+            query_filter: QueryFilter::Allow(vec!["Page".into(), "%71".into()]),
+            normalize_percent_encoding: true,
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&allow, "/?PAGE=2&q=x&utm_source=y"),
+            "/?page=2&q=x"
+        );
+        let deny = UriNormalizer {
+            query_filter: QueryFilter::Deny(vec!["UTM_Source".into()]),
+            case_insensitive: true,
+            ..Default::default()
+        };
+        assert_eq!(normalize(&deny, "/?utm_source=y&Page=2"), "/?page=2");
+        let case_sensitive = UriNormalizer {
+            query_filter: QueryFilter::Deny(vec!["utm_source".into()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            normalize(&case_sensitive, "/?UTM_SOURCE=y&utm_source=z"),
+            "/?UTM_SOURCE=y"
+        );
+    }
+}
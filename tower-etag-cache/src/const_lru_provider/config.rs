@@ -0,0 +1,140 @@
+use bytes::Bytes;
+use http::{
+    header::{
+        CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_TYPE, SET_COOKIE,
+    },
+    HeaderName, HeaderValue, StatusCode,
+};
+use http_body::Body;
+use std::time::Duration;
+
+use crate::{
+    freshness_lifetime, provider_util::key_covers, CacheKeyExtractor, CachedResponse,
+    RequestCredentials,
+};
+
+/// Configuration for [`ConstLruProvider`](super::ConstLruProvider)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ConstLruProviderConfig {
+    /// Response bodies whose size hint has no upper bound or an upper bound larger than this number of bytes
+    /// are streamed to the client while being hashed instead of being buffered into memory.
+    ///
+    /// The ETag of a streamed response is only known once the body ends, so streamed responses
+    /// are sent without `ETag` and `Last-Modified` headers. The ETag is then saved to the cache,
+    /// and sent with `HEAD` responses and used to evaluate conditional requests.
+    ///
+    /// Responses with the [`DiscardedBody`](crate::DiscardedBody) extension are read and hashed
+    /// before they are returned instead of being streamed.
+    ///
+    /// `None`, the default, buffers all response bodies so that the ETag is sent on the first response.
+    pub stream_threshold: Option<u64>,
+
+    /// Least recently used entries are evicted once the approximate total bytes
+    /// of all cached keys and ETags exceeds this, in addition to the entry-count limit `CAP`.
+    ///
+    /// `None`, the default, only evicts by entry count.
+    pub max_bytes: Option<usize>,
+
+    /// Entries expire this long after their ETag was last saved,
+    /// unless their response has a [`freshness_lifetime`](crate::freshness_lifetime)
+    /// and [`Self::use_freshness_lifetime`] is set, which takes precedence.
+    ///
+    /// `None`, the default, never expires entries unless [`Self::use_freshness_lifetime`] is set.
+    pub ttl: Option<Duration>,
+
+    /// If true, entries expire after their response's [`freshness_lifetime`](crate::freshness_lifetime)
+    /// from `Cache-Control: s-maxage`/`max-age` or `Expires`.
+    ///
+    /// A lifetime of zero, e.g. `max-age=0`, `Expires` equal to `Date` or an invalid `Expires`,
+    /// requires the response to be revalidated on every use, so the ETag is kept
+    /// with [`Self::ttl`] instead of expiring immediately.
+    ///
+    /// false, the default, ignores the response's freshness lifetime.
+    pub use_freshness_lifetime: bool,
+
+    /// If set, the bodies of buffered HTTP 200 responses are saved along with these response headers,
+    /// and `GET` and `HEAD` requests that would otherwise miss are served from the cache
+    /// without running the inner service.
+    ///
+    /// Streamed bodies, responses with `Set-Cookie` or `Cache-Control: no-store`, `no-cache` or `private`
+    /// and responses with a freshness lifetime of zero are never saved.
+    /// Neither are responses whose `Vary` names a request header the cache key does not vary on,
+    /// or `*`, and responses to requests with `Authorization` or `Cookie` headers the key does not vary on,
+    /// which are also never served saved responses, see [`RequestCredentials`].
+    /// Saved bodies count towards [`Self::max_bytes`].
+    ///
+    /// `None`, the default, only saves ETags.
+    pub full_response_headers: Option<&'static [HeaderName]>,
+
+    /// Entries saved longer than this ago are still used to respond,
+    /// but mark the request as [`StaleEntry`](crate::StaleEntry) at most once per interval
+    /// so that [`EtagCache::refresh_with`](crate::EtagCache::refresh_with) recalculates the ETag in the background.
+    ///
+    /// `None`, the default, never marks entries as stale.
+    pub refresh_after: Option<Duration>,
+}
+
+impl ConstLruProviderConfig {
+    /// Suggested [`Self::full_response_headers`]
+    pub const DEFAULT_FULL_RESPONSE_HEADERS: &'static [HeaderName] =
+        &[CONTENT_TYPE, CONTENT_ENCODING, CONTENT_LANGUAGE];
+
+    pub fn should_stream<B: Body>(&self, body: &B) -> bool {
+        match self.stream_threshold {
+            None => false,
+            Some(threshold) => body
+                .size_hint()
+                .upper()
+                .is_none_or(|upper| upper > threshold),
+        }
+    }
+
+    /// Returns the response to save to the cache, if any.
+    ///
+    /// `credentials` are those of the response's request.
+    pub(super) fn cached_response<K: CacheKeyExtractor>(
+        &self,
+        parts: &http::response::Parts,
+        body: &Bytes,
+        credentials: RequestCredentials,
+        key_extractor: &K,
+    ) -> Option<CachedResponse> {
+        let header_names = self.full_response_headers?;
+        if parts.status != StatusCode::OK
+            || parts.headers.contains_key(SET_COOKIE)
+            || is_uncacheable(&parts.headers)
+            // must be revalidated with the inner service on every use
+            || freshness_lifetime(&parts.headers) == Some(Duration::ZERO)
+            // would be served to requests the response does not apply to
+            || !key_covers(key_extractor, &parts.headers, credentials)
+        {
+            return None;
+        }
+        let mut headers = http::HeaderMap::new();
+        for name in header_names {
+            for val in parts.headers.get_all(name) {
+                headers.append(name.clone(), val.clone());
+            }
+        }
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+        Some(CachedResponse {
+            headers,
+            body: body.clone(),
+        })
+    }
+}
+
+/// Whether `Cache-Control` has `no-store`, `no-cache` or `private`
+fn is_uncacheable(headers: &http::HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|s| s.split(','))
+        .map(|directive| directive.trim().split('=').next().unwrap_or_default())
+        .any(|name| {
+            ["no-store", "no-cache", "private"]
+                .iter()
+                .any(|d| name.eq_ignore_ascii_case(d))
+        })
+}
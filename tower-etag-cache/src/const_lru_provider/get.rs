@@ -9,26 +9,21 @@ use pin_project::pin_project;
 use tokio::sync::oneshot;
 use tower_service::Service;
 
-use crate::CacheGetResponse;
+use crate::{CacheGetResponse, CacheKeyExtractor};
 
 use super::{
     err::ConstLruProviderError, ConstLruProviderCacheKey, ConstLruProviderHandle,
-    ConstLruProviderReq, ConstLruProviderRes, ReqTup,
+    ConstLruProviderReq, ConstLruProviderRes, ReqTup, ResResult,
 };
 
 #[pin_project]
-pub struct ConstLruProviderGetFuture<ReqBody, ResBody: Body> {
+pub struct ConstLruProviderGetFuture<ReqBody, ResBody: Body, Key = ConstLruProviderCacheKey> {
     #[pin]
-    resp_rx: oneshot::Receiver<
-        Result<ConstLruProviderRes<ReqBody>, ConstLruProviderError<ResBody::Error>>,
-    >,
+    resp_rx: oneshot::Receiver<ResResult<ReqBody, ResBody, Key>>,
 }
 
-impl<ReqBody, ResBody: Body> Future for ConstLruProviderGetFuture<ReqBody, ResBody> {
-    type Output = Result<
-        CacheGetResponse<ReqBody, ConstLruProviderCacheKey>,
-        ConstLruProviderError<ResBody::Error>,
-    >;
+impl<ReqBody, ResBody: Body, Key> Future for ConstLruProviderGetFuture<ReqBody, ResBody, Key> {
+    type Output = Result<CacheGetResponse<ReqBody, Key>, ConstLruProviderError<ResBody::Error>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         self.project().resp_rx.poll(cx).map(|oneshot_result| {
@@ -45,16 +40,16 @@ impl<ReqBody, ResBody: Body> Future for ConstLruProviderGetFuture<ReqBody, ResBo
     }
 }
 
-impl<ReqBody, ResBody: Body> Service<http::Request<ReqBody>>
-    for ConstLruProviderHandle<ReqBody, ResBody>
+impl<ReqBody, ResBody: Body, G, K: CacheKeyExtractor> Service<http::Request<ReqBody>>
+    for ConstLruProviderHandle<ReqBody, ResBody, G, K>
 where
-    ReqTup<ReqBody, ResBody>: Send,
+    ReqTup<ReqBody, ResBody, K::Key>: Send,
 {
-    type Response = CacheGetResponse<ReqBody, ConstLruProviderCacheKey>;
+    type Response = CacheGetResponse<ReqBody, K::Key>;
 
     type Error = ConstLruProviderError<ResBody::Error>;
 
-    type Future = ConstLruProviderGetFuture<ReqBody, ResBody>;
+    type Future = ConstLruProviderGetFuture<ReqBody, ResBody, K::Key>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.req_tx
@@ -0,0 +1,72 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use http_body::Body;
+use pin_project::pin_project;
+use tokio::sync::oneshot;
+use tower_service::Service;
+
+use crate::{CacheInvalidateRequest, CacheKeyExtractor};
+
+use super::{
+    err::ConstLruProviderError, ConstLruProviderCacheKey, ConstLruProviderHandle,
+    ConstLruProviderReq, ConstLruProviderRes, ReqTup, ResResult,
+};
+
+#[pin_project]
+pub struct ConstLruProviderInvalidateFuture<ReqBody, ResBody: Body, Key = ConstLruProviderCacheKey>
+{
+    #[pin]
+    resp_rx: oneshot::Receiver<ResResult<ReqBody, ResBody, Key>>,
+}
+
+impl<ReqBody, ResBody: Body, Key> Future
+    for ConstLruProviderInvalidateFuture<ReqBody, ResBody, Key>
+{
+    type Output = Result<(), ConstLruProviderError<ResBody::Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.project().resp_rx.poll(cx).map(|oneshot_result| {
+            oneshot_result.map_or_else(
+                |e| Err(ConstLruProviderError::OneshotRecv(e)),
+                |result| {
+                    result.map(|en| match en {
+                        ConstLruProviderRes::Invalidate => (),
+                        _ => unreachable!(),
+                    })
+                },
+            )
+        })
+    }
+}
+
+impl<ReqBody, ResBody: Body, G, K: CacheKeyExtractor> Service<CacheInvalidateRequest>
+    for ConstLruProviderHandle<ReqBody, ResBody, G, K>
+where
+    ReqTup<ReqBody, ResBody, K::Key>: Send,
+{
+    type Response = ();
+
+    type Error = ConstLruProviderError<ResBody::Error>;
+
+    type Future = ConstLruProviderInvalidateFuture<ReqBody, ResBody, K::Key>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.req_tx
+            .poll_reserve(cx)
+            .map_err(|_| ConstLruProviderError::MpscSend)
+    }
+
+    fn call(&mut self, req: CacheInvalidateRequest) -> Self::Future {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        // safe to ignore err since resp_tx will be dropped
+        // here and next poll of ConstLruProviderInvalidateFuture will fail
+        let _ = self
+            .req_tx
+            .send_item((ConstLruProviderReq::Invalidate(req), resp_tx));
+        ConstLruProviderInvalidateFuture { resp_rx }
+    }
+}
@@ -1,69 +1,197 @@
 //! An in-memory [`CacheProvider`] backed by a single `ConstLru`
 
-use const_lru::ConstLru;
-use http::{
-    header::{ETAG, IF_NONE_MATCH, LAST_MODIFIED},
-    HeaderMap, HeaderValue,
-};
+use const_lru::{ConstLru, InsertReplaced};
+use http::Method;
 use http_body::Body;
-use http_body_util::BodyExt;
 use num_traits::{PrimInt, Unsigned};
-use std::{alloc::alloc, alloc::Layout, error::Error, ptr::addr_of_mut, time::SystemTime};
-use time::{format_description::well_known::Rfc2822, OffsetDateTime};
+use std::{
+    alloc::alloc,
+    alloc::Layout,
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    future::poll_fn,
+    ptr::addr_of_mut,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
 use tokio::sync::{mpsc, oneshot};
 use tokio_util::sync::PollSender;
 
 use crate::{
-    base64_blake3_body_etag::base64_blake3_body_etag,
-    simple_etag_cache_key::{calc_simple_etag_cache_key, SimpleEtagCacheKey},
-    CacheGetResponse, CacheGetResponseResult, CacheProvider,
+    base64_blake3_body_etag::Base64Blake3EtagGenerator,
+    provider_util::{
+        cache_get_result, entry_size, precondition_check_result, purge_matches, CacheEntry,
+        KeyIndex,
+    },
+    simple_etag_cache_key::{SimpleEtagCacheKey, SimpleEtagCacheKeyExtractor},
+    CacheGetResponse, CacheGetResponseResult, CacheInvalidateRequest, CacheKeyExtractor,
+    CacheProvider, CachedResponse, EntityTag, Epoch, EtagGenerator, PreconditionCheck,
+    PurgeRequest, SingleFlightGuard, SingleFlightRequest, StaleEntry,
 };
 
+mod config;
 mod err;
 mod get;
+mod invalidate;
+mod purge;
 mod put;
+mod single_flight;
 mod tres_body;
 
+use single_flight::ReleaseOnDrop;
+
+pub use config::*;
 pub use err::*;
 pub use get::*;
+pub use invalidate::*;
+pub use purge::*;
 pub use put::*;
 pub use tres_body::*;
 
+/// The default cache key type
 pub type ConstLruProviderCacheKey = SimpleEtagCacheKey;
 
 /// Tuple containing the request to the provider and the oneshot
 /// sender for the provider to send the response to
-pub type ReqTup<ReqBody, ResBody> = (
-    ConstLruProviderReq<ReqBody, ResBody>,
-    oneshot::Sender<
-        Result<ConstLruProviderRes<ReqBody>, ConstLruProviderError<<ResBody as Body>::Error>>,
-    >,
+pub type ReqTup<ReqBody, ResBody, Key = ConstLruProviderCacheKey> = (
+    ConstLruProviderReq<ReqBody, Key>,
+    oneshot::Sender<ResResult<ReqBody, ResBody, Key>>,
 );
 
+/// What the provider sends back through the oneshot channel
+pub type ResResult<ReqBody, ResBody, Key = ConstLruProviderCacheKey> =
+    Result<ConstLruProviderRes<ReqBody, Key>, ConstLruProviderError<<ResBody as Body>::Error>>;
+
+/// [`ConstLruProviderReq::Get`] requests and their oneshot senders
+/// waiting on the in-flight miss for each key
+type InFlight<ReqBody, ResBody, Key> = BTreeMap<
+    Key,
+    Vec<(
+        http::Request<ReqBody>,
+        oneshot::Sender<ResResult<ReqBody, ResBody, Key>>,
+    )>,
+>;
+
 #[derive(Debug)]
-pub enum ConstLruProviderReq<ReqBody, ResBody> {
+pub enum ConstLruProviderReq<ReqBody, Key = ConstLruProviderCacheKey> {
     Get(http::Request<ReqBody>),
-    Put(ConstLruProviderCacheKey, http::Response<ResBody>),
+    /// Saves an ETag calculated by [`ConstLruProviderPutFuture`] or [`ConstLruProviderStreamingBody`]
+    ///
+    /// The `u64` is the [`Epoch::generation`] the ETag was generated in
+    ///
+    /// The `Option<Duration>` is the entry's TTL,
+    /// `None` to use [`ConstLruProviderConfig::ttl`]
+    ///
+    /// The `Option<CachedResponse>` is the response to serve from the cache,
+    /// see [`ConstLruProviderConfig::full_response_headers`]
+    ///
+    /// The `Box<[String]>` is the entry's sorted and deduplicated [`CacheTags`](crate::CacheTags)
+    Insert(
+        Key,
+        EntityTag,
+        u64,
+        Option<Duration>,
+        Option<CachedResponse>,
+        Box<[String]>,
+    ),
+    /// Returns the saved ETag and last modified time for a key if it has not expired
+    /// and was saved in the current [`Epoch`]
+    Peek(Key),
+    Invalidate(CacheInvalidateRequest),
+    /// Removes the selected entries, sent by [`ConstLruProviderPurgeHandle`]
+    Purge(PurgeRequest<Key>),
 }
 
+// only ever moved once through a oneshot channel, not worth boxing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
-pub enum ConstLruProviderRes<ReqBody> {
-    Get(CacheGetResponse<ReqBody, ConstLruProviderCacheKey>),
-    Put(http::Response<ConstLruProviderTResBody>),
+pub enum ConstLruProviderRes<ReqBody, Key = ConstLruProviderCacheKey> {
+    Get(CacheGetResponse<ReqBody, Key>),
+    /// The saved entry's last modified time
+    Insert(SystemTime),
+    Peek(Option<(EntityTag, SystemTime)>),
+    Invalidate,
+    /// The number of entries removed
+    Purge(usize),
 }
 
 /// A basic in-memory ConstLru-backed cache provider.
 ///
 /// Meant to be a single instance communicated with using a `tokio::sync::mpsc::channel` via [`ConstLruProviderHandle`]
 ///
-/// Uses [`SimpleEtagCacheKey`] as key type.
+/// Uses keys calculated by a [`CacheKeyExtractor`], [`SimpleEtagCacheKeyExtractor`] by default.
 ///
 /// Also stores the `SystemTime` of when the cache entry was created, which serves as the response's
 /// last-modified header value
-pub struct ConstLruProvider<ReqBody, ResBody: Body, const CAP: usize, I: PrimInt + Unsigned = usize>
-{
-    const_lru: ConstLru<ConstLruProviderCacheKey, (String, SystemTime), CAP, I>,
-    req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>,
+///
+/// Response bodies are read and hashed by the caller's [`ConstLruProviderPutFuture`],
+/// only the resulting ETag is sent to the provider.
+/// ETags are calculated by the handle's [`EtagGenerator`], [`Base64Blake3EtagGenerator`] by default.
+///
+/// Evicts entries by entry count, and by approximate total bytes if
+/// [`ConstLruProviderConfig::max_bytes`] is set.
+///
+/// Entries expire after [`ConstLruProviderConfig::ttl`], or their response's [`freshness_lifetime`](crate::freshness_lifetime)
+/// if [`ConstLruProviderConfig::use_freshness_lifetime`] is set, and are then treated as misses.
+///
+/// Can also serve HTTP 200 responses from the cache without running the inner service
+/// if [`ConstLruProviderConfig::full_response_headers`] is set.
+///
+/// Prefixes ETags with the handle's [`Epoch`], if set, and treats entries saved in earlier epochs as misses.
+///
+/// Indexes entries by the [`CacheTags`](crate::CacheTags) of their responses
+/// for [`ConstLruProviderPurgeHandle::purge_tag`].
+///
+/// Marks entries for background refresh if [`ConstLruProviderConfig::refresh_after`] is set.
+///
+/// Coalesces concurrent `GET` and `HEAD` misses for the same key that are marked as [`SingleFlightRequest`]s
+/// if [`ConstLruProviderConfig::full_response_headers`] is set, since only a saved response can serve them all:
+/// the first `GET` miss is returned with a [`SingleFlightGuard`] and the others wait until it is dropped,
+/// after which they look up the cache again without being coalesced.
+/// The guard is held until the response's ETag has been saved, at the end of the body if it is streamed.
+///
+/// Generates weak ETags for responses with the [`WeakEtag`](crate::WeakEtag) extension.
+/// Lookups evaluate the request's conditional headers against the cached ETag and `SystemTime`
+/// using [`Validators::evaluate`](crate::Validators::evaluate),
+/// or against all entries for the request's URI if it is a [`PreconditionCheck`].
+pub struct ConstLruProvider<
+    ReqBody,
+    ResBody: Body,
+    const CAP: usize,
+    I: PrimInt + Unsigned = usize,
+    K: CacheKeyExtractor = SimpleEtagCacheKeyExtractor,
+> {
+    const_lru: ConstLru<K::Key, CacheEntry, CAP, I>,
+    req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody, K::Key>>,
+    config: ConstLruProviderConfig,
+    key_extractor: K,
+    /// Approximate total bytes of all entries, shared with [`ConstLruProviderHandle`]s
+    bytes: Arc<AtomicUsize>,
+    /// Shared with [`ConstLruProviderHandle`]s
+    epoch: Epoch,
+    /// Keys of the entries carrying each tag
+    tag_index: KeyIndex<BTreeSet<K::Key>>,
+    /// Keys of the entries for each URI, see [`CacheKeyExtractor::key_uri`]
+    uri_index: KeyIndex<BTreeSet<K::Key>>,
+    /// Requests waiting on the in-flight miss for each key
+    in_flight: InFlight<ReqBody, ResBody, K::Key>,
+    /// Receives the keys of in-flight misses whose [`SingleFlightGuard`] was dropped
+    release_rx: mpsc::UnboundedReceiver<K::Key>,
+    /// Cloned into [`SingleFlightGuard`]s
+    release_tx: mpsc::UnboundedSender<K::Key>,
+}
+
+/// Next message for the [`ConstLruProvider`] to handle
+// only ever moved out of the receiver and matched on, not worth boxing
+#[allow(clippy::large_enum_variant)]
+enum Next<ReqBody, ResBody: Body, Key> {
+    Req(ReqTup<ReqBody, ResBody, Key>),
+    /// The key of an in-flight miss whose [`SingleFlightGuard`] was dropped
+    Release(Key),
 }
 
 impl<
@@ -71,10 +199,12 @@ impl<
         ResBody: Send + Body + 'static,
         const CAP: usize,
         I: PrimInt + Unsigned + Send + 'static,
-    > ConstLruProvider<ReqBody, ResBody, CAP, I>
+        K: CacheKeyExtractor + Clone + Send + 'static,
+    > ConstLruProvider<ReqBody, ResBody, CAP, I, K>
 where
     <ResBody as Body>::Data: Send,
     <ResBody as Body>::Error: Error + Send + Sync,
+    K::Key: Ord + Clone + Send + Sync,
 {
     /// Allocates and creates a ConstLruProvider on the heap and returns the [`CacheProvider`] handle to it.
     ///
@@ -83,18 +213,98 @@ where
     /// Should be called once on server init
     ///
     /// `req_buffer` is the size of the `mpsc::channel` connecting [`ConstLruProviderHandle`] to [`ConstLruProvider`]
-    pub fn init(req_buffer: usize) -> ConstLruProviderHandle<ReqBody, ResBody> {
+    pub fn init(
+        req_buffer: usize,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody, Base64Blake3EtagGenerator, K>
+    where
+        K: Default,
+    {
+        Self::init_with_config(req_buffer, ConstLruProviderConfig::default())
+    }
+
+    /// [`Self::init`] with a non-default [`ConstLruProviderConfig`]
+    pub fn init_with_config(
+        req_buffer: usize,
+        config: ConstLruProviderConfig,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody, Base64Blake3EtagGenerator, K>
+    where
+        K: Default,
+    {
+        Self::init_with_generator(req_buffer, config, Base64Blake3EtagGenerator)
+    }
+
+    /// [`Self::init_with_config`] with a non-default [`EtagGenerator`]
+    pub fn init_with_generator<G: EtagGenerator>(
+        req_buffer: usize,
+        config: ConstLruProviderConfig,
+        generator: G,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody, G, K>
+    where
+        K: Default,
+    {
+        Self::init_with_key_extractor(req_buffer, config, generator, K::default())
+    }
+
+    /// [`Self::init_with_generator`] with a non-default [`CacheKeyExtractor`]
+    pub fn init_with_key_extractor<G: EtagGenerator>(
+        req_buffer: usize,
+        config: ConstLruProviderConfig,
+        generator: G,
+        key_extractor: K,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody, G, K> {
+        Self::init_with_epoch(
+            req_buffer,
+            config,
+            generator,
+            key_extractor,
+            Epoch::default(),
+        )
+    }
+
+    /// [`Self::init_with_key_extractor`] starting in an existing [`Epoch`],
+    /// e.g. one created with [`Epoch::new`] to prefix ETags from the first response on
+    pub fn init_with_epoch<G: EtagGenerator>(
+        req_buffer: usize,
+        config: ConstLruProviderConfig,
+        generator: G,
+        key_extractor: K,
+        epoch: Epoch,
+    ) -> ConstLruProviderHandle<ReqBody, ResBody, G, K> {
         let (req_tx, req_rx) = mpsc::channel(req_buffer);
+        let (release_tx, release_rx) = mpsc::unbounded_channel();
+
+        let bytes = Arc::new(AtomicUsize::new(0));
 
-        let mut this = Self::boxed(req_rx);
+        let mut this = Self::boxed(
+            req_rx,
+            release_rx,
+            release_tx,
+            config,
+            key_extractor.clone(),
+            bytes.clone(),
+            epoch.clone(),
+        );
         tokio::spawn(async move { this.run().await });
 
         ConstLruProviderHandle {
             req_tx: PollSender::new(req_tx),
+            config,
+            bytes,
+            epoch,
+            generator,
+            key_extractor,
         }
     }
 
-    fn boxed(req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody>>) -> Box<Self> {
+    fn boxed(
+        req_rx: mpsc::Receiver<ReqTup<ReqBody, ResBody, K::Key>>,
+        release_rx: mpsc::UnboundedReceiver<K::Key>,
+        release_tx: mpsc::UnboundedSender<K::Key>,
+        config: ConstLruProviderConfig,
+        key_extractor: K,
+        bytes: Arc<AtomicUsize>,
+        epoch: Epoch,
+    ) -> Box<Self> {
         // directly alloc so that a large ConstLru does not trigger stack overflow
         unsafe {
             let ptr = alloc(Layout::new::<Self>()) as *mut Self;
@@ -102,135 +312,362 @@ where
             ConstLru::init_at_alloc(const_lru_ptr);
             let req_rx_ptr = addr_of_mut!((*ptr).req_rx);
             req_rx_ptr.write(req_rx);
+            addr_of_mut!((*ptr).config).write(config);
+            addr_of_mut!((*ptr).key_extractor).write(key_extractor);
+            addr_of_mut!((*ptr).bytes).write(bytes);
+            addr_of_mut!((*ptr).epoch).write(epoch);
+            addr_of_mut!((*ptr).tag_index).write(KeyIndex::new());
+            addr_of_mut!((*ptr).uri_index).write(KeyIndex::new());
+            addr_of_mut!((*ptr).in_flight).write(BTreeMap::new());
+            addr_of_mut!((*ptr).release_rx).write(release_rx);
+            addr_of_mut!((*ptr).release_tx).write(release_tx);
             Box::from_raw(ptr)
         }
     }
 
-    /// long-running loop
+    /// long-running loop. Every request is handled synchronously
+    /// so that no single request holds up the others.
     async fn run(&mut self) {
-        while let Some((req, resp_tx)) = self.req_rx.recv().await {
-            let res = match req {
-                ConstLruProviderReq::Get(req) => {
-                    self.on_get_request(req).map(ConstLruProviderRes::Get)
+        while let Some(next) = poll_fn(|cx| self.poll_next(cx)).await {
+            match next {
+                Next::Req((req, resp_tx)) => self.on_request(req, resp_tx),
+                Next::Release(key) => {
+                    // requests sent before the guard was dropped, such as the miss's ETag insert,
+                    // are handled before the waiting requests look up the cache again
+                    while let Ok((req, resp_tx)) = self.req_rx.try_recv() {
+                        self.on_request(req, resp_tx);
+                    }
+                    self.on_release(&key);
                 }
-                ConstLruProviderReq::Put(key, resp) => self
-                    .on_put_request(key, resp)
-                    .await
-                    .map(ConstLruProviderRes::Put),
-            };
-            // ignore error if resp_rx dropped
-            let _ = resp_tx.send(res);
+            }
         }
         // exits when all req_tx dropped
     }
 
-    fn on_get_request(
+    /// Prefers requests over releases
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Next<ReqBody, ResBody, K::Key>>> {
+        if let Poll::Ready(req_tup) = self.req_rx.poll_recv(cx) {
+            return Poll::Ready(req_tup.map(Next::Req));
+        }
+        // never closed since self.release_tx is never dropped
+        self.release_rx
+            .poll_recv(cx)
+            .map(|key| key.map(Next::Release))
+    }
+
+    fn on_request(
         &mut self,
-        req: http::Request<ReqBody>,
-    ) -> Result<
-        CacheGetResponse<ReqBody, ConstLruProviderCacheKey>,
-        ConstLruProviderError<ResBody::Error>,
-    > {
-        let key = calc_simple_etag_cache_key(&req);
-        let (cache_etag, last_modified) = match self.const_lru.get(&key) {
-            Some(e) => e,
-            None => {
-                return Ok(CacheGetResponse {
-                    req,
-                    result: crate::CacheGetResponseResult::Miss(key),
-                })
+        req: ConstLruProviderReq<ReqBody, K::Key>,
+        resp_tx: oneshot::Sender<ResResult<ReqBody, ResBody, K::Key>>,
+    ) {
+        let res = match req {
+            ConstLruProviderReq::Get(req) => {
+                // responds or waits on an in-flight miss
+                self.on_get_request(req, resp_tx);
+                return;
+            }
+            ConstLruProviderReq::Insert(key, etag, epoch, ttl, response, tags) => {
+                let ttl = ttl.or(self.config.ttl);
+                let last_modified = self.insert_etag(key, etag, epoch, ttl, response, tags);
+                Ok(ConstLruProviderRes::Insert(last_modified))
+            }
+            ConstLruProviderReq::Peek(key) => {
+                let saved = self
+                    .const_lru
+                    .get(&key)
+                    .filter(|e| !e.is_outdated(self.epoch.generation()))
+                    .map(|e| (e.etag.clone(), e.last_modified));
+                Ok(ConstLruProviderRes::Peek(saved))
+            }
+            ConstLruProviderReq::Invalidate(req) => {
+                self.on_invalidate_request(req);
+                Ok(ConstLruProviderRes::Invalidate)
+            }
+            ConstLruProviderReq::Purge(req) => {
+                Ok(ConstLruProviderRes::Purge(self.on_purge_request(req)))
             }
         };
-        let if_none_match_iter = req.headers().get_all(IF_NONE_MATCH);
-        for etag in if_none_match_iter {
-            let etag_str = match etag.to_str() {
-                Ok(s) => s,
-                Err(_) => continue,
-            };
-            if etag_str == cache_etag {
-                let mut header_map = HeaderMap::new();
-                Self::set_response_headers(&mut header_map, etag.clone(), *last_modified);
-                return Ok(CacheGetResponse {
-                    req,
-                    result: CacheGetResponseResult::Hit(header_map),
-                });
+        // ignore error if resp_rx dropped
+        let _ = resp_tx.send(res);
+    }
+
+    /// Sends the lookup result through `resp_tx`, unless the request is a
+    /// [`SingleFlightRequest`] miss for a key whose miss is already in flight
+    fn on_get_request(
+        &mut self,
+        mut req: http::Request<ReqBody>,
+        resp_tx: oneshot::Sender<ResResult<ReqBody, ResBody, K::Key>>,
+    ) {
+        let key = self.key_extractor.extract(&req);
+        if req.extensions().get::<PreconditionCheck>().is_some() {
+            let uri = self.key_extractor.uri_string(req.uri());
+            let entries = self
+                .uri_index
+                .get(&uri)
+                .into_iter()
+                .flatten()
+                .filter(|k| self.key_extractor.matches_uri(k, req.uri()))
+                .filter_map(|k| self.const_lru.get_untouched(k));
+            let result = precondition_check_result(&req, key, entries, self.epoch.generation());
+            // ignore error if resp_rx dropped
+            let _ = resp_tx.send(Ok(ConstLruProviderRes::Get(CacheGetResponse {
+                req,
+                result,
+            })));
+            return;
+        }
+        let result = cache_get_result(
+            &req,
+            key.clone(),
+            self.const_lru.get(&key),
+            self.epoch.generation(),
+            &self.key_extractor,
+        );
+        if let (Some(refresh_after), Some(entry)) =
+            (self.config.refresh_after, self.const_lru.get_mut(&key))
+        {
+            let is_hit = matches!(
+                result,
+                CacheGetResponseResult::Hit(..) | CacheGetResponseResult::Full(..)
+            );
+            if is_hit && entry.should_refresh(refresh_after) {
+                req.extensions_mut().insert(StaleEntry);
+            }
+        }
+        // waiting requests without If-None-Match can only be served a saved response
+        let coalesce = matches!(result, CacheGetResponseResult::Miss(_))
+            && self.config.full_response_headers.is_some()
+            && matches!(*req.method(), Method::GET | Method::HEAD)
+            && req.extensions().get::<SingleFlightRequest>().is_some();
+        if coalesce {
+            if let Some(waiting) = self.in_flight.get_mut(&key) {
+                waiting.push((req, resp_tx));
+                return;
+            }
+            // HEAD responses save nothing for the requests waiting on them
+            if req.method() == Method::GET {
+                self.in_flight.insert(key.clone(), Vec::new());
+                req.extensions_mut()
+                    .insert(SingleFlightGuard::new(ReleaseOnDrop::new(
+                        key,
+                        self.release_tx.clone(),
+                    )));
             }
         }
-        Ok(CacheGetResponse {
+        // ignore error if resp_rx dropped
+        let _ = resp_tx.send(Ok(ConstLruProviderRes::Get(CacheGetResponse {
             req,
-            result: CacheGetResponseResult::Miss(key),
-        })
+            result,
+        })));
     }
 
-    async fn on_put_request(
+    /// Looks up the cache again for the requests that were waiting on the in-flight miss for `key`.
+    ///
+    /// They are no longer coalesced so that they do not wait on each other
+    /// if the miss's response was not cached.
+    fn on_release(&mut self, key: &K::Key) {
+        let waiting = match self.in_flight.remove(key) {
+            Some(w) => w,
+            None => return,
+        };
+        // skip requests whose caller has gone away
+        for (mut req, resp_tx) in waiting.into_iter().filter(|(_, tx)| !tx.is_closed()) {
+            req.extensions_mut().remove::<SingleFlightRequest>();
+            self.on_get_request(req, resp_tx);
+        }
+    }
+
+    /// Returns the last modified time of the cache entry for `key` after saving `etag`, `response` and `tags` to it
+    fn insert_etag(
         &mut self,
-        key: ConstLruProviderCacheKey,
-        resp: http::Response<ResBody>,
-    ) -> Result<http::Response<ConstLruProviderTResBody>, ConstLruProviderError<ResBody::Error>>
-    {
-        let (mut parts, body) = resp.into_parts();
-        let body_bytes = BodyExt::collect(body)
-            .await
-            .map_err(ConstLruProviderError::ReadResBody)?
-            .to_bytes();
+        key: K::Key,
+        etag: EntityTag,
+        epoch: u64,
+        ttl: Option<Duration>,
+        response: Option<CachedResponse>,
+        tags: Box<[String]>,
+    ) -> SystemTime {
+        let (last_modified, new_size) = match self.const_lru.get_mut(&key) {
+            Some(curr_val) => {
+                self.bytes
+                    .fetch_sub(entry_size::<K>(&key, curr_val), Ordering::Relaxed);
+                self.tag_index.remove(&key, &curr_val.tags);
+                self.tag_index.insert(&key, &tags);
+                curr_val.update(etag, epoch, ttl, response, tags);
+                (curr_val.last_modified, entry_size::<K>(&key, curr_val))
+            }
+            None => {
+                self.tag_index.insert(&key, &tags);
+                self.uri_index.insert(&key, &[K::key_uri(&key)]);
+                let entry = CacheEntry::new(etag, epoch, ttl, response, tags);
+                let last_modified = entry.last_modified;
+                let new_size = entry_size::<K>(&key, &entry);
+                if let Some(InsertReplaced::LruEvicted(k, e)) = self.const_lru.insert(key, entry) {
+                    self.bytes
+                        .fetch_sub(entry_size::<K>(&k, &e), Ordering::Relaxed);
+                    self.tag_index.remove(&k, &e.tags);
+                    self.uri_index.remove(&k, &[K::key_uri(&k)]);
+                }
+                (last_modified, new_size)
+            }
+        };
+        self.bytes.fetch_add(new_size, Ordering::Relaxed);
+        self.evict_over_budget();
+        last_modified
+    }
 
-        let etag = base64_blake3_body_etag(&body_bytes);
-        // unwrap-safety: base64 should always be valid ascii
-        let etag_str = etag.to_str().unwrap();
+    /// Evicts least recently used entries until the total bytes is within `max_bytes`
+    fn evict_over_budget(&mut self) {
+        let max_bytes = match self.config.max_bytes {
+            Some(m) => m,
+            None => return,
+        };
+        while self.bytes.load(Ordering::Relaxed) > max_bytes {
+            let lru_key = match self.const_lru.iter().next_back() {
+                Some((k, _)) => k.clone(),
+                None => return,
+            };
+            self.remove(&lru_key);
+        }
+    }
 
-        let curr_val = self
-            .const_lru
-            .entry(key)
-            .or_insert_with(|| (etag_str.to_owned(), SystemTime::now()));
+    /// Returns true if there was an entry for `key`
+    fn remove(&mut self, key: &K::Key) -> bool {
+        match self.const_lru.remove(key) {
+            Some(entry) => {
+                self.bytes
+                    .fetch_sub(entry_size::<K>(key, &entry), Ordering::Relaxed);
+                self.tag_index.remove(key, &entry.tags);
+                self.uri_index.remove(key, &[K::key_uri(key)]);
+                true
+            }
+            None => false,
+        }
+    }
 
-        // don't modify if cached etag is already the same
-        if curr_val.0 != etag_str {
-            curr_val.0 = etag_str.to_owned();
-            curr_val.1 = SystemTime::now();
+    /// Returns the number of entries removed
+    fn remove_matching(&mut self, matches: impl Fn(&K, &K::Key, &CacheEntry) -> bool) -> usize {
+        let keys: Vec<K::Key> = self
+            .const_lru
+            .iter()
+            .filter(|(k, e)| matches(&self.key_extractor, k, e))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in keys.iter() {
+            self.remove(k);
         }
+        keys.len()
+    }
 
-        let last_modified = curr_val.1;
-        Self::set_response_headers(&mut parts.headers, etag, last_modified);
+    /// Removes all entries whose uri matches `uri`, returning the number of entries removed
+    fn remove_uri(&mut self, uri: &http::Uri) -> usize {
+        let uri_string = self.key_extractor.uri_string(uri);
+        let keys: Vec<K::Key> = self
+            .uri_index
+            .get(&uri_string)
+            .into_iter()
+            .flatten()
+            .filter(|k| self.key_extractor.matches_uri(k, uri))
+            .cloned()
+            .collect();
+        for k in keys.iter() {
+            self.remove(k);
+        }
+        keys.len()
+    }
 
-        Ok(http::Response::from_parts(parts, body_bytes.into()))
+    /// Removes all entries whose uri matches any of the request's URIs
+    fn on_invalidate_request(&mut self, CacheInvalidateRequest { uris }: CacheInvalidateRequest) {
+        for uri in uris.iter() {
+            self.remove_uri(uri);
+        }
     }
 
-    fn set_response_headers(
-        headers_mut: &mut HeaderMap,
-        etag_val: HeaderValue,
-        last_modified_val: SystemTime,
-    ) {
-        headers_mut.append(ETAG, etag_val);
-        let last_modified_val = OffsetDateTime::from(last_modified_val)
-            .format(&Rfc2822)
-            .unwrap();
-        headers_mut.append(
-            LAST_MODIFIED,
-            HeaderValue::from_str(&last_modified_val).unwrap(),
-        );
-        SimpleEtagCacheKey::set_response_headers(headers_mut);
+    /// Returns the number of entries removed
+    fn on_purge_request(&mut self, req: PurgeRequest<K::Key>) -> usize {
+        match req {
+            PurgeRequest::Key(key) => self.remove(&key).into(),
+            PurgeRequest::Uri(uri) => self.remove_uri(&uri),
+            PurgeRequest::Tag(tag) => {
+                let keys = self.tag_index.take(&tag);
+                keys.iter().filter(|k| self.remove(k)).count()
+            }
+            req => {
+                self.remove_matching(|key_extractor, k, e| purge_matches(&req, k, e, key_extractor))
+            }
+        }
     }
 }
 
 // SERVICE HANDLE
 
-pub struct ConstLruProviderHandle<ReqBody, ResBody: Body> {
-    req_tx: PollSender<ReqTup<ReqBody, ResBody>>,
+pub struct ConstLruProviderHandle<
+    ReqBody,
+    ResBody: Body,
+    G = Base64Blake3EtagGenerator,
+    K: CacheKeyExtractor = SimpleEtagCacheKeyExtractor,
+> {
+    req_tx: PollSender<ReqTup<ReqBody, ResBody, K::Key>>,
+    config: ConstLruProviderConfig,
+    bytes: Arc<AtomicUsize>,
+    epoch: Epoch,
+    generator: G,
+    key_extractor: K,
 }
 
-impl<ReqBody, ResBody: Body> Clone for ConstLruProviderHandle<ReqBody, ResBody> {
+impl<ReqBody, ResBody: Body, G, K: CacheKeyExtractor>
+    ConstLruProviderHandle<ReqBody, ResBody, G, K>
+{
+    /// Approximate total bytes of the keys and ETags currently in the cache
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// The provider's deployment epoch, shared by all clones of this handle.
+    ///
+    /// `handle.epoch().set(Some("build-42"))` invalidates every previously generated ETag.
+    pub fn epoch(&self) -> &Epoch {
+        &self.epoch
+    }
+}
+
+impl<ReqBody, ResBody: Body, G, K: CacheKeyExtractor> ConstLruProviderHandle<ReqBody, ResBody, G, K>
+where
+    ReqTup<ReqBody, ResBody, K::Key>: Send,
+{
+    /// Returns a [`ConstLruProviderPurgeHandle`] for removing entries from outside the tower stack
+    pub fn purge_handle(&self) -> ConstLruProviderPurgeHandle<ReqBody, ResBody, K::Key> {
+        // unwrap-safety: PollSender only drops its Sender when closed, which the handle never does
+        let req_tx = self.req_tx.get_ref().unwrap().clone();
+        ConstLruProviderPurgeHandle { req_tx }
+    }
+}
+
+impl<ReqBody, ResBody: Body, G: Clone, K: CacheKeyExtractor + Clone> Clone
+    for ConstLruProviderHandle<ReqBody, ResBody, G, K>
+{
     fn clone(&self) -> Self {
         Self {
             req_tx: self.req_tx.clone(),
+            config: self.config,
+            bytes: self.bytes.clone(),
+            epoch: self.epoch.clone(),
+            generator: self.generator.clone(),
+            key_extractor: self.key_extractor.clone(),
         }
     }
 }
 
-impl<ReqBody: Send, ResBody: Body + Send> CacheProvider<ReqBody, ResBody>
-    for ConstLruProviderHandle<ReqBody, ResBody>
+impl<
+        ReqBody: Send,
+        ResBody: Body + Send,
+        G: EtagGenerator + Clone,
+        K: CacheKeyExtractor + Clone,
+    > CacheProvider<ReqBody, ResBody> for ConstLruProviderHandle<ReqBody, ResBody, G, K>
 where
     ResBody::Error: Send,
+    K::Key: Clone + Send,
 {
-    type Key = ConstLruProviderCacheKey;
-    type TResBody = ConstLruProviderTResBody;
+    type Key = K::Key;
+    type TResBody = ConstLruProviderTResBody<ReqBody, ResBody, G, K::Key>;
 }
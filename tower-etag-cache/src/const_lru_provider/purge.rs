@@ -0,0 +1,101 @@
+use http_body::Body;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::PurgeRequest;
+
+use super::{
+    err::ConstLruProviderError, ConstLruProviderCacheKey, ConstLruProviderReq, ConstLruProviderRes,
+    ReqTup,
+};
+
+/// Removes entries from a [`ConstLruProvider`](super::ConstLruProvider) outside of the tower stack,
+/// e.g. when content is published.
+///
+/// Created with [`ConstLruProviderHandle::purge_handle`](super::ConstLruProviderHandle::purge_handle).
+/// Keeps the provider running like a [`ConstLruProviderHandle`](super::ConstLruProviderHandle).
+///
+/// Every method returns the number of entries removed.
+pub struct ConstLruProviderPurgeHandle<ReqBody, ResBody: Body, Key = ConstLruProviderCacheKey> {
+    pub(super) req_tx: mpsc::Sender<ReqTup<ReqBody, ResBody, Key>>,
+}
+
+impl<ReqBody, ResBody: Body, Key> ConstLruProviderPurgeHandle<ReqBody, ResBody, Key> {
+    /// Sends `req` to the provider and waits for it to be handled
+    pub async fn purge(
+        &self,
+        req: PurgeRequest<Key>,
+    ) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        let (resp_tx, resp_rx) = oneshot::channel();
+        self.req_tx
+            .send((ConstLruProviderReq::Purge(req), resp_tx))
+            .await
+            .map_err(|_| ConstLruProviderError::MpscSend)?;
+        match resp_rx
+            .await
+            .map_err(ConstLruProviderError::OneshotRecv)??
+        {
+            ConstLruProviderRes::Purge(removed) => Ok(removed),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Removes the entry with exactly this key
+    pub async fn purge_key(
+        &self,
+        key: Key,
+    ) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        self.purge(PurgeRequest::Key(key)).await
+    }
+
+    /// Removes all entries for `uri`, see [`CacheKeyExtractor::matches_uri`](crate::CacheKeyExtractor::matches_uri)
+    pub async fn purge_uri(
+        &self,
+        uri: http::Uri,
+    ) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        self.purge(PurgeRequest::Uri(uri)).await
+    }
+
+    /// Removes all entries whose URI path starts with `prefix`,
+    /// see [`CacheKeyExtractor::matches_path_prefix`](crate::CacheKeyExtractor::matches_path_prefix).
+    ///
+    /// Matches plain string prefixes, so `/blog` also matches `/blog-archive`.
+    pub async fn purge_path_prefix(
+        &self,
+        prefix: impl Into<String>,
+    ) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        self.purge(PurgeRequest::PathPrefix(prefix.into())).await
+    }
+
+    /// Removes all entries carrying `tag`, see [`CacheTags`](crate::CacheTags)
+    pub async fn purge_tag(
+        &self,
+        tag: impl Into<String>,
+    ) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        self.purge(PurgeRequest::Tag(tag.into())).await
+    }
+
+    /// Removes all entries whose key `predicate` returns true for.
+    ///
+    /// `predicate` runs in the provider's request loop, so it should be cheap.
+    pub async fn purge_where(
+        &self,
+        predicate: impl Fn(&Key) -> bool + Send + 'static,
+    ) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        self.purge(PurgeRequest::Predicate(Box::new(predicate)))
+            .await
+    }
+
+    /// Removes all entries
+    pub async fn clear(&self) -> Result<usize, ConstLruProviderError<ResBody::Error>> {
+        self.purge(PurgeRequest::All).await
+    }
+}
+
+// manual impl since ReqBody and ResBody are not required to be Clone
+impl<ReqBody, ResBody: Body, Key> Clone for ConstLruProviderPurgeHandle<ReqBody, ResBody, Key> {
+    fn clone(&self) -> Self {
+        Self {
+            req_tx: self.req_tx.clone(),
+        }
+    }
+}
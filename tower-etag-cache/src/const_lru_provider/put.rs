@@ -1,56 +1,292 @@
 use std::{
     future::Future,
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
+    time::Duration,
 };
 
+use bytes::{Buf, Bytes};
+use http::header::ETAG;
 use http_body::Body;
+use http_body_util::{combinators::Collect, BodyExt};
 use pin_project::pin_project;
 use tokio::sync::oneshot;
+use tokio_util::sync::PollSender;
 use tower_service::Service;
 
+use crate::{
+    provider_util::{entry_tags, response_ttl, set_cache_headers},
+    CacheKeyExtractor, CachedResponse, DiscardedBody, EntityTag, EpochValue, EtagGenerator,
+    EtagHasher, HeadResponse, RequestCredentials, SingleFlightGuard, WeakEtag,
+};
+
 use super::{
-    err::ConstLruProviderError, ConstLruProviderCacheKey, ConstLruProviderHandle,
-    ConstLruProviderReq, ConstLruProviderRes, ConstLruProviderTResBody, ReqTup,
+    err::ConstLruProviderError, ConstLruProviderConfig, ConstLruProviderHandle,
+    ConstLruProviderReq, ConstLruProviderRes, ConstLruProviderStreamingBody,
+    ConstLruProviderTResBody, ReqTup, ResResult,
 };
 
+/// Reads and hashes the response body in the caller's task.
+/// Only the final ETag insert goes through the [`ConstLruProvider`](super::ConstLruProvider).
+///
+/// Bodies that are streamed are returned as [`ConstLruProviderStreamingBody`]s without an `ETag` header,
+/// since their ETag is only known once they end.
 #[pin_project]
-pub struct ConstLruProviderPutFuture<ReqBody, ResBody: Body> {
+pub struct ConstLruProviderPutFuture<ReqBody, ResBody: Body, G: EtagGenerator, K: CacheKeyExtractor>
+{
     #[pin]
-    resp_rx: oneshot::Receiver<
-        Result<ConstLruProviderRes<ReqBody>, ConstLruProviderError<ResBody::Error>>,
-    >,
+    state: ConstLruProviderPutFutureState<ReqBody, ResBody, G, K::Key>,
+    req_tx: PollSender<ReqTup<ReqBody, ResBody, K::Key>>,
+    config: ConstLruProviderConfig,
+    generator: G,
+    key_extractor: K,
+    key: Option<K::Key>,
+    parts: Option<http::response::Parts>,
+    weak: bool,
+    /// The provider's epoch when the response was received
+    epoch: EpochValue,
+    /// The response's freshness lifetime, see [`ConstLruProviderConfig::use_freshness_lifetime`]
+    ttl: Option<Duration>,
+    /// The response's [`CacheTags`](crate::CacheTags), taken when the ETag is saved
+    tags: Box<[String]>,
+    /// The credentials of the response's request
+    credentials: RequestCredentials,
+    /// Held until the ETag has been saved, moved into the [`ConstLruProviderStreamingBody`]
+    /// if the body is streamed
+    single_flight_guard: Option<SingleFlightGuard>,
+}
+
+#[pin_project(project = ConstLruProviderPutFutureStateProj)]
+enum ConstLruProviderPutFutureState<ReqBody, ResBody: Body, G: EtagGenerator, Key> {
+    /// Buffering the response body
+    Collect {
+        #[pin]
+        collect: Collect<ResBody>,
+    },
+
+    /// Reading and hashing a body that is not sent to the client, see [`DiscardedBody`]
+    Discard {
+        #[pin]
+        body: ResBody,
+        /// Taken once the body ends
+        hasher: Option<G::Hasher>,
+    },
+
+    /// Returning a body to be streamed to the client while being hashed
+    Stream { body: Option<ResBody> },
+
+    /// Waiting for a slot in the channel to the provider
+    ReserveInsert {
+        etag: Option<EntityTag>,
+        body: Bytes,
+        /// The response to serve from the cache
+        response: Option<CachedResponse>,
+    },
+
+    /// Waiting for the provider to save the ETag
+    Insert {
+        #[pin]
+        resp_rx: oneshot::Receiver<ResResult<ReqBody, ResBody, Key>>,
+        etag: EntityTag,
+        body: Bytes,
+    },
+
+    /// Waiting for the provider to return the ETag saved by a previous `GET` response
+    /// for a `HEAD` response
+    Peek {
+        #[pin]
+        resp_rx: oneshot::Receiver<ResResult<ReqBody, ResBody, Key>>,
+        body: Option<ResBody>,
+    },
+
+    /// Buffering the body of a `HEAD` response, which is passed through as is
+    Head {
+        #[pin]
+        collect: Collect<ResBody>,
+    },
 }
 
-impl<ReqBody, ResBody: Body> Future for ConstLruProviderPutFuture<ReqBody, ResBody> {
-    type Output =
-        Result<http::Response<ConstLruProviderTResBody>, ConstLruProviderError<ResBody::Error>>;
+impl<ReqBody, ResBody: Body, G: EtagGenerator, K: CacheKeyExtractor> Future
+    for ConstLruProviderPutFuture<ReqBody, ResBody, G, K>
+where
+    ReqTup<ReqBody, ResBody, K::Key>: Send,
+    K::Key: Clone,
+{
+    type Output = Result<
+        http::Response<ConstLruProviderTResBody<ReqBody, ResBody, G, K::Key>>,
+        ConstLruProviderError<ResBody::Error>,
+    >;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project().resp_rx.poll(cx).map(|oneshot_result| {
-            oneshot_result.map_or_else(
-                |e| Err(ConstLruProviderError::OneshotRecv(e)),
-                |result| {
-                    result.map(|en| match en {
-                        ConstLruProviderRes::Put(r) => r,
+        let mut this = self.project();
+        loop {
+            match this.state.as_mut().project() {
+                ConstLruProviderPutFutureStateProj::Collect { collect } => {
+                    let body = ready!(collect.poll(cx))
+                        .map_err(ConstLruProviderError::ReadResBody)?
+                        .to_bytes();
+                    // unwrap-safety: parts only taken on completion
+                    let etag = this.generator.generate(this.parts.as_ref().unwrap(), &body);
+                    let etag = finish_etag(etag, *this.weak, this.epoch);
+                    let response = this.config.cached_response(
+                        this.parts.as_ref().unwrap(),
+                        &body,
+                        *this.credentials,
+                        this.key_extractor,
+                    );
+                    this.state
+                        .set(ConstLruProviderPutFutureState::ReserveInsert {
+                            etag: Some(etag),
+                            body,
+                            response,
+                        });
+                }
+                ConstLruProviderPutFutureStateProj::Discard { mut body, hasher } => {
+                    while let Some(frame) = ready!(body.as_mut().poll_frame(cx)) {
+                        let frame = frame.map_err(ConstLruProviderError::ReadResBody)?;
+                        if let Ok(mut data) = frame.into_data() {
+                            // unwrap-safety: hasher only taken once the body ends
+                            hasher
+                                .as_mut()
+                                .unwrap()
+                                .update(&data.copy_to_bytes(data.remaining()));
+                        }
+                    }
+                    // unwrap-safety: hasher only taken here, once
+                    let etag = hasher.take().unwrap().finish();
+                    let etag = finish_etag(etag, *this.weak, this.epoch);
+                    this.state
+                        .set(ConstLruProviderPutFutureState::ReserveInsert {
+                            etag: Some(etag),
+                            body: Bytes::new(),
+                            response: None,
+                        });
+                }
+                ConstLruProviderPutFutureStateProj::Stream { body } => {
+                    // unwrap-safety: parts, key and body only taken once, here
+                    let mut parts = this.parts.take().unwrap();
+                    // ETag of the body is not known until it ends
+                    this.key_extractor.set_response_headers(&mut parts.headers);
+                    let body = ConstLruProviderStreamingBody::new(
+                        body.take().unwrap(),
+                        this.generator.hasher(&parts),
+                        this.key.take().unwrap(),
+                        *this.weak,
+                        this.epoch.clone(),
+                        *this.ttl,
+                        std::mem::take(this.tags),
+                        this.req_tx.get_ref().cloned(),
+                        this.single_flight_guard.take(),
+                    );
+                    return Poll::Ready(Ok(http::Response::from_parts(
+                        parts,
+                        ConstLruProviderTResBody::Streaming(body),
+                    )));
+                }
+                ConstLruProviderPutFutureStateProj::ReserveInsert {
+                    etag,
+                    body,
+                    response,
+                } => {
+                    ready!(this.req_tx.poll_reserve(cx))
+                        .map_err(|_| ConstLruProviderError::MpscSend)?;
+                    // unwrap-safety: key and etag are only taken here, once
+                    let key = this.key.take().unwrap();
+                    let etag = etag.take().unwrap();
+                    let (resp_tx, resp_rx) = oneshot::channel();
+                    this.req_tx
+                        .send_item((
+                            ConstLruProviderReq::Insert(
+                                key,
+                                etag.clone(),
+                                this.epoch.generation(),
+                                *this.ttl,
+                                response.take(),
+                                std::mem::take(this.tags),
+                            ),
+                            resp_tx,
+                        ))
+                        .map_err(|_| ConstLruProviderError::MpscSend)?;
+                    let body = std::mem::take(body);
+                    this.state.set(ConstLruProviderPutFutureState::Insert {
+                        resp_rx,
+                        etag,
+                        body,
+                    });
+                }
+                ConstLruProviderPutFutureStateProj::Insert {
+                    resp_rx,
+                    etag,
+                    body,
+                } => {
+                    let last_modified = match ready!(resp_rx.poll(cx))
+                        .map_err(ConstLruProviderError::OneshotRecv)??
+                    {
+                        ConstLruProviderRes::Insert(last_modified) => last_modified,
+                        _ => unreachable!(),
+                    };
+                    // waiting requests can now be served the saved entry
+                    this.single_flight_guard.take();
+                    // unwrap-safety: parts only taken once, on completion
+                    let mut parts = this.parts.take().unwrap();
+                    parts.headers.append(ETAG, etag.to_header_value());
+                    set_cache_headers(&mut parts.headers, last_modified, this.key_extractor);
+                    return Poll::Ready(Ok(http::Response::from_parts(
+                        parts,
+                        std::mem::take(body).into(),
+                    )));
+                }
+                ConstLruProviderPutFutureStateProj::Peek { resp_rx, body } => {
+                    let saved = match ready!(resp_rx.poll(cx))
+                        .map_err(ConstLruProviderError::OneshotRecv)??
+                    {
+                        ConstLruProviderRes::Peek(saved) => saved,
                         _ => unreachable!(),
-                    })
-                },
-            )
-        })
+                    };
+                    // unwrap-safety: parts only taken once, on completion
+                    let parts = this.parts.as_mut().unwrap();
+                    match saved {
+                        Some((etag, last_modified)) => {
+                            parts.headers.append(ETAG, etag.to_header_value());
+                            set_cache_headers(
+                                &mut parts.headers,
+                                last_modified,
+                                this.key_extractor,
+                            );
+                        }
+                        None => this.key_extractor.set_response_headers(&mut parts.headers),
+                    }
+                    // unwrap-safety: body only taken once, here
+                    let collect = body.take().unwrap().collect();
+                    this.state
+                        .set(ConstLruProviderPutFutureState::Head { collect });
+                }
+                ConstLruProviderPutFutureStateProj::Head { collect } => {
+                    let body = ready!(collect.poll(cx))
+                        .map_err(ConstLruProviderError::ReadResBody)?
+                        .to_bytes();
+                    // unwrap-safety: parts only taken once, on completion
+                    return Poll::Ready(Ok(http::Response::from_parts(
+                        this.parts.take().unwrap(),
+                        body.into(),
+                    )));
+                }
+            }
+        }
     }
 }
 
-impl<ReqBody, ResBody: Body> Service<(ConstLruProviderCacheKey, http::Response<ResBody>)>
-    for ConstLruProviderHandle<ReqBody, ResBody>
+impl<ReqBody, ResBody: Body, G: EtagGenerator + Clone, K: CacheKeyExtractor + Clone>
+    Service<(K::Key, http::Response<ResBody>)> for ConstLruProviderHandle<ReqBody, ResBody, G, K>
 where
-    ReqTup<ReqBody, ResBody>: Send,
+    ReqTup<ReqBody, ResBody, K::Key>: Send,
+    K::Key: Clone,
 {
-    type Response = http::Response<ConstLruProviderTResBody>;
+    type Response = http::Response<ConstLruProviderTResBody<ReqBody, ResBody, G, K::Key>>;
 
     type Error = ConstLruProviderError<ResBody::Error>;
 
-    type Future = ConstLruProviderPutFuture<ReqBody, ResBody>;
+    type Future = ConstLruProviderPutFuture<ReqBody, ResBody, G, K>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.req_tx
@@ -58,16 +294,69 @@ where
             .map_err(|_| ConstLruProviderError::MpscSend)
     }
 
-    fn call(
-        &mut self,
-        (key, resp): (ConstLruProviderCacheKey, http::Response<ResBody>),
-    ) -> Self::Future {
-        let (resp_tx, resp_rx) = oneshot::channel();
-        // safe to ignore err since resp_tx will be dropped
-        // here and next poll of ConstLruProviderPutFuture will fail
-        let _ = self
-            .req_tx
-            .send_item((ConstLruProviderReq::Put(key, resp), resp_tx));
-        ConstLruProviderPutFuture { resp_rx }
+    fn call(&mut self, (key, resp): (K::Key, http::Response<ResBody>)) -> Self::Future {
+        let (mut parts, body) = resp.into_parts();
+        let weak = parts.extensions.remove::<WeakEtag>().is_some();
+        let head = parts.extensions.remove::<HeadResponse>().is_some();
+        let discarded = parts.extensions.remove::<DiscardedBody>().is_some();
+        let credentials = parts
+            .extensions
+            .remove::<RequestCredentials>()
+            .unwrap_or_default();
+        let single_flight_guard = parts.extensions.remove::<SingleFlightGuard>();
+        let ttl = response_ttl(&parts.headers, self.config.use_freshness_lifetime);
+        let tags = entry_tags(&parts.extensions);
+        if !head {
+            // slot is reserved again once the body has been hashed
+            self.req_tx.abort_send();
+        }
+        let state = match (head, self.config.should_stream(&body)) {
+            // HEAD responses only need the saved ETag
+            (true, _) => {
+                let (resp_tx, resp_rx) = oneshot::channel();
+                // safe to ignore err since resp_tx will be dropped
+                // here and next poll of ConstLruProviderPutFuture will fail
+                let _ = self
+                    .req_tx
+                    .send_item((ConstLruProviderReq::Peek(key.clone()), resp_tx));
+                ConstLruProviderPutFutureState::Peek {
+                    resp_rx,
+                    body: Some(body),
+                }
+            }
+            // never polled by the server, so read to completion here instead of being streamed
+            (false, true) if discarded => ConstLruProviderPutFutureState::Discard {
+                hasher: Some(self.generator.hasher(&parts)),
+                body,
+            },
+            (false, true) => ConstLruProviderPutFutureState::Stream { body: Some(body) },
+            (false, false) => ConstLruProviderPutFutureState::Collect {
+                collect: body.collect(),
+            },
+        };
+        ConstLruProviderPutFuture {
+            state,
+            req_tx: self.req_tx.clone(),
+            config: self.config,
+            generator: self.generator.clone(),
+            key_extractor: self.key_extractor.clone(),
+            key: Some(key),
+            parts: Some(parts),
+            weak,
+            epoch: self.epoch.get(),
+            ttl,
+            tags,
+            credentials,
+            single_flight_guard,
+        }
     }
 }
+
+/// Weakens `etag` if needed and prefixes it with the epoch
+fn finish_etag(etag: EntityTag, weak: bool, epoch: &EpochValue) -> EntityTag {
+    let etag = match weak {
+        true => etag.into_weak(),
+        false => etag,
+    };
+    epoch.apply(etag)
+}
@@ -0,0 +1,29 @@
+use tokio::sync::mpsc;
+
+/// Wrapped by the [`SingleFlightGuard`](crate::SingleFlightGuard) of an in-flight miss.
+///
+/// Tells the [`ConstLruProvider`](super::ConstLruProvider) to wake the requests waiting on it when dropped.
+pub(super) struct ReleaseOnDrop<Key> {
+    /// Only taken on drop
+    key: Option<Key>,
+    /// Unbounded so that dropping never waits for capacity or needs a tokio runtime
+    release_tx: mpsc::UnboundedSender<Key>,
+}
+
+impl<Key> ReleaseOnDrop<Key> {
+    pub fn new(key: Key, release_tx: mpsc::UnboundedSender<Key>) -> Self {
+        Self {
+            key: Some(key),
+            release_tx,
+        }
+    }
+}
+
+impl<Key> Drop for ReleaseOnDrop<Key> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            // ignore error if the provider has shut down
+            let _ = self.release_tx.send(key);
+        }
+    }
+}
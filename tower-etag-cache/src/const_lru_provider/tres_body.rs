@@ -1,38 +1,272 @@
 use std::{
-    convert::Infallible,
+    fmt::{self, Debug},
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
+    time::Duration,
 };
 
-use bytes::Bytes;
-use http_body::{Body, Frame};
+use bytes::{Buf, Bytes};
+use http_body::{Body, Frame, SizeHint};
 use pin_project::pin_project;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::PollSender;
 
-/// Newtype for Bytes to impl Body for
+use crate::{
+    base64_blake3_body_etag::Base64Blake3EtagGenerator, EpochValue, EtagGenerator, EtagHasher,
+    SingleFlightGuard,
+};
+
+use super::{ConstLruProviderCacheKey, ConstLruProviderReq, ReqTup};
+
+/// Response body type of [`ConstLruProviderHandle`](super::ConstLruProviderHandle)
 #[derive(Debug)]
-#[pin_project]
-pub struct ConstLruProviderTResBody(Bytes);
+#[pin_project(project = ConstLruProviderTResBodyProj)]
+pub enum ConstLruProviderTResBody<
+    ReqBody,
+    ResBody: Body,
+    G: EtagGenerator = Base64Blake3EtagGenerator,
+    Key = ConstLruProviderCacheKey,
+> {
+    /// The entire response body, whose ETag has already been calculated and saved
+    Buffered(Bytes),
+
+    /// The response body, hashed as it is streamed to the client
+    Streaming(#[pin] ConstLruProviderStreamingBody<ReqBody, ResBody, G, Key>),
+}
 
-impl From<Bytes> for ConstLruProviderTResBody {
+impl<ReqBody, ResBody: Body, G: EtagGenerator, Key> From<Bytes>
+    for ConstLruProviderTResBody<ReqBody, ResBody, G, Key>
+{
     fn from(value: Bytes) -> Self {
-        Self(value)
+        Self::Buffered(value)
     }
 }
 
-impl Body for ConstLruProviderTResBody {
+impl<
+        ReqBody: Send + 'static,
+        ResBody: Body + Send + 'static,
+        G: EtagGenerator,
+        Key: Send + 'static,
+    > Body for ConstLruProviderTResBody<ReqBody, ResBody, G, Key>
+where
+    ResBody::Data: Buf,
+    ResBody::Error: Send,
+{
     type Data = Bytes;
 
-    type Error = Infallible;
+    type Error = ResBody::Error;
 
     fn poll_frame(
         self: Pin<&mut Self>,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
-        let b = std::mem::take(self.project().0);
-        if b.is_empty() {
-            Poll::Ready(None)
-        } else {
-            Poll::Ready(Some(Ok(Frame::data(b))))
+        match self.project() {
+            ConstLruProviderTResBodyProj::Buffered(b) => {
+                let b = std::mem::take(b);
+                if b.is_empty() {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Ok(Frame::data(b))))
+                }
+            }
+            ConstLruProviderTResBodyProj::Streaming(s) => s.poll_frame(cx),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self {
+            Self::Buffered(b) => b.is_empty(),
+            Self::Streaming(s) => s.is_end_stream(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            Self::Buffered(b) => SizeHint::with_exact(b.len() as u64),
+            Self::Streaming(s) => s.size_hint(),
+        }
+    }
+}
+
+type ReqSender<ReqBody, ResBody, Key> = mpsc::Sender<ReqTup<ReqBody, ResBody, Key>>;
+
+/// Boxed since it is only needed once the body ends
+type PendingInsert<ReqBody, ResBody, Key> = Box<(
+    PollSender<ReqTup<ReqBody, ResBody, Key>>,
+    ReqTup<ReqBody, ResBody, Key>,
+)>;
+
+/// Wraps a response body, feeding its data frames to an [`EtagHasher`] as they are polled.
+///
+/// Saves the calculated ETag to the [`ConstLruProvider`](super::ConstLruProvider) once the body ends.
+/// Nothing is saved if the body errors or is dropped before it ends.
+#[pin_project]
+pub struct ConstLruProviderStreamingBody<
+    ReqBody,
+    ResBody: Body,
+    G: EtagGenerator = Base64Blake3EtagGenerator,
+    Key = ConstLruProviderCacheKey,
+> {
+    #[pin]
+    body: ResBody,
+    hasher: G::Hasher,
+    weak: bool,
+    /// The provider's epoch when the response was received
+    epoch: EpochValue,
+    ttl: Option<Duration>,
+    /// Sorted and deduplicated [`CacheTags`](crate::CacheTags) of the response
+    tags: Box<[String]>,
+    /// Taken once the body ends
+    insert: Option<(Key, ReqSender<ReqBody, ResBody, Key>)>,
+    /// Set once the body ends, until the ETag has been sent to the provider
+    sending: Option<PendingInsert<ReqBody, ResBody, Key>>,
+    /// Dropped once the ETag has been sent to the provider, or the body errors or is dropped
+    single_flight_guard: Option<SingleFlightGuard>,
+}
+
+// manual impl since EtagHasher is not required to be Debug
+impl<ReqBody, ResBody: Body + Debug, G: EtagGenerator, Key> Debug
+    for ConstLruProviderStreamingBody<ReqBody, ResBody, G, Key>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConstLruProviderStreamingBody")
+            .field("body", &self.body)
+            .field("weak", &self.weak)
+            .field("epoch", &self.epoch)
+            .field("ttl", &self.ttl)
+            .field("tags", &self.tags)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<ReqBody, ResBody: Body, G: EtagGenerator, Key>
+    ConstLruProviderStreamingBody<ReqBody, ResBody, G, Key>
+{
+    /// `req_tx` is `None` if the provider has shut down, in which case the ETag is not saved.
+    ///
+    /// `single_flight_guard` is held until the ETag has been sent to the provider,
+    /// so that requests waiting on this response look up the cache after it has been saved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        body: ResBody,
+        hasher: G::Hasher,
+        key: Key,
+        weak: bool,
+        epoch: EpochValue,
+        ttl: Option<Duration>,
+        tags: Box<[String]>,
+        req_tx: Option<ReqSender<ReqBody, ResBody, Key>>,
+        single_flight_guard: Option<SingleFlightGuard>,
+    ) -> Self {
+        Self {
+            body,
+            hasher,
+            weak,
+            epoch,
+            ttl,
+            tags,
+            insert: req_tx.map(|tx| (key, tx)),
+            sending: None,
+            single_flight_guard,
+        }
+    }
+}
+
+impl<
+        ReqBody: Send + 'static,
+        ResBody: Body + Send + 'static,
+        G: EtagGenerator,
+        Key: Send + 'static,
+    > Body for ConstLruProviderStreamingBody<ReqBody, ResBody, G, Key>
+where
+    ResBody::Data: Buf,
+    ResBody::Error: Send,
+{
+    type Data = Bytes;
+
+    type Error = ResBody::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if this.sending.is_some() {
+            ready!(poll_send(this.sending, cx));
+            this.single_flight_guard.take();
+            return Poll::Ready(None);
+        }
+        match this.body.poll_frame(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(Ok(frame))) => {
+                let hasher = this.hasher;
+                Poll::Ready(Some(Ok(frame.map_data(|mut data| {
+                    let bytes = data.copy_to_bytes(data.remaining());
+                    hasher.update(&bytes);
+                    bytes
+                }))))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.insert.take();
+                this.single_flight_guard.take();
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                if let Some((key, req_tx)) = this.insert.take() {
+                    let etag = this.hasher.finish();
+                    let etag = match this.weak {
+                        true => etag.into_weak(),
+                        false => etag,
+                    };
+                    let etag = this.epoch.apply(etag);
+                    // provider's response is not needed
+                    let (resp_tx, _) = oneshot::channel();
+                    let req_tup = (
+                        ConstLruProviderReq::Insert(
+                            key,
+                            etag,
+                            this.epoch.generation(),
+                            *this.ttl,
+                            None,
+                            std::mem::take(this.tags),
+                        ),
+                        resp_tx,
+                    );
+                    *this.sending = Some(Box::new((PollSender::new(req_tx), req_tup)));
+                    ready!(poll_send(this.sending, cx));
+                }
+                this.single_flight_guard.take();
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    /// Not ended until the ETag has been saved,
+    /// to make sure this body is polled to completion
+    fn is_end_stream(&self) -> bool {
+        self.insert.is_none() && self.sending.is_none() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// Waits for room in the provider's channel instead of spawning a task to send,
+/// so that ending the body never requires a tokio runtime.
+///
+/// The ETag is not saved if the provider has shut down.
+fn poll_send<T: Send + 'static>(
+    sending: &mut Option<Box<(PollSender<T>, T)>>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    if let Some(pending) = sending {
+        let reserved = ready!(pending.0.poll_reserve(cx));
+        // unwrap-safety: just checked to be Some
+        let (mut req_tx, req_tup) = *sending.take().unwrap();
+        if reserved.is_ok() {
+            let _ = req_tx.send_item(req_tup);
         }
     }
+    Poll::Ready(())
 }
@@ -0,0 +1,252 @@
+use http::{header::AsHeaderName, HeaderMap, HeaderValue};
+use std::str::FromStr;
+
+use super::{is_ows, EntityTag, EntityTagError};
+
+/// Parsed value of an `If-None-Match` or `If-Match` request header:
+///
+/// `If-None-Match = "*" / #entity-tag`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EntityTagCondition {
+    /// `*`, matches any current representation
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl EntityTagCondition {
+    /// Parses and combines all the values of header `name` in `headers`,
+    /// since a list header may be split across multiple field lines.
+    ///
+    /// Returns `Ok(None)` if the header is not present
+    pub fn from_headers(
+        headers: &HeaderMap,
+        name: impl AsHeaderName,
+    ) -> Result<Option<Self>, EntityTagError> {
+        let values = headers.get_all(name);
+        let mut iter = values.iter();
+        let Some(first) = iter.next() else {
+            return Ok(None);
+        };
+        let mut condition = Self::try_from(first)?;
+        for value in iter {
+            // field lines combine into a single list, which `*` cannot be part of
+            match (&mut condition, Self::try_from(value)?) {
+                (Self::Tags(tags), Self::Tags(t)) => tags.extend(t),
+                _ => return Err(EntityTagError::WildcardInList),
+            }
+        }
+        Ok(Some(condition))
+    }
+
+    /// Returns true if this condition is `*` or
+    /// any of its entity tags weakly matches `etag`.
+    ///
+    /// This is the comparison `If-None-Match` uses.
+    pub fn matches_weak(&self, etag: &EntityTag) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags.iter().any(|t| t.weak_eq(etag)),
+        }
+    }
+
+    /// Returns true if this condition is `*` or
+    /// any of its entity tags strongly matches `etag`.
+    ///
+    /// This is the comparison `If-Match` uses.
+    pub fn matches_strong(&self, etag: &EntityTag) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Tags(tags) => tags.iter().any(|t| t.strong_eq(etag)),
+        }
+    }
+}
+
+impl FromStr for EntityTagCondition {
+    type Err = EntityTagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_matches(is_ows);
+        if s == "*" {
+            return Ok(Self::Any);
+        }
+        let mut tags = Vec::new();
+        let mut rest = s;
+        loop {
+            // list syntax allows empty elements
+            rest = rest.trim_start_matches(|c| is_ows(c) || c == ',');
+            if rest.is_empty() {
+                break;
+            }
+            // `*` as a list element rather than the entire value
+            let wildcard = rest
+                .strip_prefix('*')
+                .map(|r| r.trim_start_matches(is_ows))
+                .is_some_and(|r| r.is_empty() || r.starts_with(','));
+            if wildcard {
+                return Err(EntityTagError::WildcardInList);
+            }
+            let (tag, r) = EntityTag::parse_prefix(rest)?;
+            tags.push(tag);
+            rest = r.trim_start_matches(is_ows);
+            if !rest.is_empty() && !rest.starts_with(',') {
+                return Err(EntityTagError::TrailingChars);
+            }
+        }
+        Ok(Self::Tags(tags))
+    }
+}
+
+impl TryFrom<&HeaderValue> for EntityTagCondition {
+    type Error = EntityTagError;
+
+    fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value
+            .to_str()
+            .map_err(|_| EntityTagError::NonAscii)?
+            .parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::IF_NONE_MATCH;
+
+    use super::*;
+
+    fn strong(s: &str) -> EntityTag {
+        EntityTag::strong(s).unwrap()
+    }
+
+    fn weak(s: &str) -> EntityTag {
+        EntityTag::weak(s).unwrap()
+    }
+
+    #[test]
+    fn parse_any() {
+        assert_eq!("*".parse(), Ok(EntityTagCondition::Any));
+        assert_eq!(" * ".parse(), Ok(EntityTagCondition::Any));
+    }
+
+    #[test]
+    fn parse_lists() {
+        assert_eq!(
+            "\"a\", W/\"b\",\"c\"".parse(),
+            Ok(EntityTagCondition::Tags(vec![
+                strong("a"),
+                weak("b"),
+                strong("c")
+            ]))
+        );
+        // empty list elements are allowed
+        assert_eq!(
+            ", \"a\",, \"b\" ,".parse(),
+            Ok(EntityTagCondition::Tags(vec![strong("a"), strong("b")]))
+        );
+        // commas are valid etagc
+        assert_eq!(
+            "\"a,b\"".parse(),
+            Ok(EntityTagCondition::Tags(vec![strong("a,b")]))
+        );
+        assert_eq!("".parse(), Ok(EntityTagCondition::Tags(vec![])));
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!(
+            "\"a\" \"b\"".parse::<EntityTagCondition>(),
+            Err(EntityTagError::TrailingChars)
+        );
+        assert_eq!(
+            "\"a\", b".parse::<EntityTagCondition>(),
+            Err(EntityTagError::Unquoted)
+        );
+        assert_eq!(
+            "\"a\", *".parse::<EntityTagCondition>(),
+            Err(EntityTagError::WildcardInList)
+        );
+        assert_eq!(
+            "*, \"a\"".parse::<EntityTagCondition>(),
+            Err(EntityTagError::WildcardInList)
+        );
+        assert_eq!(
+            "*a".parse::<EntityTagCondition>(),
+            Err(EntityTagError::Unquoted)
+        );
+        assert_eq!(
+            "\"a".parse::<EntityTagCondition>(),
+            Err(EntityTagError::Unquoted)
+        );
+    }
+
+    #[test]
+    fn from_headers_combines_field_lines() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            EntityTagCondition::from_headers(&headers, IF_NONE_MATCH),
+            Ok(None)
+        );
+
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("\"a\""));
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("W/\"b\", \"c\""));
+        assert_eq!(
+            EntityTagCondition::from_headers(&headers, IF_NONE_MATCH),
+            Ok(Some(EntityTagCondition::Tags(vec![
+                strong("a"),
+                weak("b"),
+                strong("c")
+            ])))
+        );
+
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("oops"));
+        assert_eq!(
+            EntityTagCondition::from_headers(&headers, IF_NONE_MATCH),
+            Err(EntityTagError::Unquoted)
+        );
+    }
+
+    #[test]
+    fn from_headers_any() {
+        let mut headers = HeaderMap::new();
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert_eq!(
+            EntityTagCondition::from_headers(&headers, IF_NONE_MATCH),
+            Ok(Some(EntityTagCondition::Any))
+        );
+
+        // `*` cannot be combined with a list
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("\"a\""));
+        assert_eq!(
+            EntityTagCondition::from_headers(&headers, IF_NONE_MATCH),
+            Err(EntityTagError::WildcardInList)
+        );
+
+        // in any order
+        let mut headers = HeaderMap::new();
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("\"a\""));
+        headers.append(IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert_eq!(
+            EntityTagCondition::from_headers(&headers, IF_NONE_MATCH),
+            Err(EntityTagError::WildcardInList)
+        );
+    }
+
+    #[test]
+    fn matches() {
+        let any = EntityTagCondition::Any;
+        assert!(any.matches_weak(&weak("x")));
+        assert!(any.matches_strong(&weak("x")));
+
+        let list: EntityTagCondition = "W/\"a\", \"b\"".parse().unwrap();
+        assert!(list.matches_weak(&strong("a")));
+        assert!(list.matches_weak(&weak("a")));
+        assert!(!list.matches_strong(&strong("a")));
+        assert!(list.matches_strong(&strong("b")));
+        assert!(!list.matches_strong(&weak("b")));
+        assert!(list.matches_weak(&weak("b")));
+        assert!(!list.matches_weak(&strong("c")));
+
+        let empty = EntityTagCondition::Tags(vec![]);
+        assert!(!empty.matches_weak(&strong("a")));
+        assert!(!empty.matches_strong(&strong("a")));
+    }
+}
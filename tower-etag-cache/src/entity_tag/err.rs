@@ -0,0 +1,36 @@
+use std::{error::Error, fmt::Display};
+
+/// Error returned when parsing an [`EntityTag`](super::EntityTag) or an
+/// [`EntityTagCondition`](super::EntityTagCondition) fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityTagError {
+    /// The header value contained non-visible-ASCII characters
+    NonAscii,
+
+    /// The opaque-tag was not enclosed in double quotes
+    Unquoted,
+
+    /// The opaque-tag contained a character that is not a valid `etagc`
+    InvalidChar(char),
+
+    /// Unexpected characters after an entity tag, such as a missing comma between list members
+    TrailingChars,
+
+    /// `*` was combined with entity tags, in the same or another field line,
+    /// but is only valid as the entire field value
+    WildcardInList,
+}
+
+impl Display for EntityTagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NonAscii => write!(f, "entity tag is not visible ASCII"),
+            Self::Unquoted => write!(f, "entity tag is not double-quoted"),
+            Self::InvalidChar(c) => write!(f, "invalid entity tag char {c:?}"),
+            Self::TrailingChars => write!(f, "unexpected chars after entity tag"),
+            Self::WildcardInList => write!(f, "* combined with entity tags"),
+        }
+    }
+}
+
+impl Error for EntityTagError {}
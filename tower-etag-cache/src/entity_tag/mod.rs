@@ -0,0 +1,215 @@
+use http::HeaderValue;
+use std::{fmt::Display, str::FromStr};
+
+mod condition;
+mod err;
+
+pub use condition::*;
+pub use err::*;
+
+/// An [RFC 9110](https://www.rfc-editor.org/rfc/rfc9110#name-etag) entity tag, e.g. `"xyzzy"` or `W/"xyzzy"`
+///
+/// The opaque-tag is stored without its enclosing double quotes.
+/// The `Display` impl outputs the quoted form suitable for use as an `ETag` header value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityTag {
+    weak: bool,
+    opaque_tag: String,
+}
+
+impl EntityTag {
+    /// `opaque_tag` should not include the enclosing double quotes
+    pub fn new(weak: bool, opaque_tag: impl Into<String>) -> Result<Self, EntityTagError> {
+        let opaque_tag = opaque_tag.into();
+        if let Some(c) = opaque_tag.chars().find(|c| !is_etagc(*c)) {
+            return Err(EntityTagError::InvalidChar(c));
+        }
+        Ok(Self { weak, opaque_tag })
+    }
+
+    /// `opaque_tag` should not include the enclosing double quotes
+    pub fn strong(opaque_tag: impl Into<String>) -> Result<Self, EntityTagError> {
+        Self::new(false, opaque_tag)
+    }
+
+    /// `opaque_tag` should not include the enclosing double quotes
+    pub fn weak(opaque_tag: impl Into<String>) -> Result<Self, EntityTagError> {
+        Self::new(true, opaque_tag)
+    }
+
+    /// Converts this entity tag into a weak entity tag with the same opaque-tag
+    pub fn into_weak(self) -> Self {
+        Self {
+            weak: true,
+            opaque_tag: self.opaque_tag,
+        }
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    /// The opaque-tag without its enclosing double quotes
+    pub fn opaque_tag(&self) -> &str {
+        &self.opaque_tag
+    }
+
+    /// [Strong comparison](https://www.rfc-editor.org/rfc/rfc9110#section-8.8.3.2):
+    /// both entity tags must not be weak and their opaque-tags must be identical
+    pub fn strong_eq(&self, other: &Self) -> bool {
+        !self.weak && !other.weak && self.opaque_tag == other.opaque_tag
+    }
+
+    /// [Weak comparison](https://www.rfc-editor.org/rfc/rfc9110#section-8.8.3.2):
+    /// opaque-tags must be identical regardless of either entity tag being weak
+    pub fn weak_eq(&self, other: &Self) -> bool {
+        self.opaque_tag == other.opaque_tag
+    }
+
+    pub fn to_header_value(&self) -> HeaderValue {
+        // unwrap-safety: opaque_tag validated to only contain visible ascii
+        HeaderValue::from_str(&self.to_string()).unwrap()
+    }
+
+    /// Parses a single entity tag at the start of `s`,
+    /// returning it along with the remaining unparsed part of `s`
+    fn parse_prefix(s: &str) -> Result<(Self, &str), EntityTagError> {
+        let (weak, s) = match s.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let s = s.strip_prefix('"').ok_or(EntityTagError::Unquoted)?;
+        let end = s.find('"').ok_or(EntityTagError::Unquoted)?;
+        let tag = Self::new(weak, &s[..end])?;
+        Ok((tag, &s[end + 1..]))
+    }
+}
+
+impl Display for EntityTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.weak {
+            f.write_str("W/")?;
+        }
+        write!(f, "\"{}\"", self.opaque_tag)
+    }
+}
+
+impl FromStr for EntityTag {
+    type Err = EntityTagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag, rest) = Self::parse_prefix(s.trim_matches(is_ows))?;
+        if !rest.is_empty() {
+            return Err(EntityTagError::TrailingChars);
+        }
+        Ok(tag)
+    }
+}
+
+impl TryFrom<&HeaderValue> for EntityTag {
+    type Error = EntityTagError;
+
+    fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value
+            .to_str()
+            .map_err(|_| EntityTagError::NonAscii)?
+            .parse()
+    }
+}
+
+/// `http::Response` extension that signals to a [`CacheProvider`](crate::CacheProvider)
+/// that the ETag it generates for the response should be weak (`W/"..."`).
+///
+/// Inserted by [`EtagCache`](crate::EtagCache) if
+/// [`PassthroughPredicate::should_weaken_etag`](crate::PassthroughPredicate::should_weaken_etag)
+/// returns true, but may also be inserted by inner services directly.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct WeakEtag;
+
+/// `etagc = %x21 / %x23-7E / obs-text`
+///
+/// obs-text is not accepted since `HeaderValue::to_str()` rejects it anyway
+fn is_etagc(c: char) -> bool {
+    c == '\x21' || ('\x23'..='\x7e').contains(&c)
+}
+
+/// `OWS = *( SP / HTAB )`
+fn is_ows(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let strong: EntityTag = "\"xyzzy\"".parse().unwrap();
+        assert!(!strong.is_weak());
+        assert_eq!(strong.opaque_tag(), "xyzzy");
+        assert_eq!(strong.to_string(), "\"xyzzy\"");
+
+        let weak: EntityTag = " W/\"xyzzy\"\t".parse().unwrap();
+        assert!(weak.is_weak());
+        assert_eq!(weak.to_string(), "W/\"xyzzy\"");
+
+        let empty: EntityTag = "\"\"".parse().unwrap();
+        assert_eq!(empty.opaque_tag(), "");
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!("xyzzy".parse::<EntityTag>(), Err(EntityTagError::Unquoted));
+        assert_eq!(
+            "\"xyzzy".parse::<EntityTag>(),
+            Err(EntityTagError::Unquoted)
+        );
+        assert_eq!(
+            "w/\"xyzzy\"".parse::<EntityTag>(),
+            Err(EntityTagError::Unquoted)
+        );
+        assert_eq!(
+            "\"a\"b".parse::<EntityTag>(),
+            Err(EntityTagError::TrailingChars)
+        );
+        assert_eq!(
+            "\"a b\"".parse::<EntityTag>(),
+            Err(EntityTagError::InvalidChar(' '))
+        );
+        assert_eq!(
+            EntityTag::strong("a\"b"),
+            Err(EntityTagError::InvalidChar('"'))
+        );
+        let non_ascii = HeaderValue::from_bytes(b"\"\xff\"").unwrap();
+        assert_eq!(
+            EntityTag::try_from(&non_ascii),
+            Err(EntityTagError::NonAscii)
+        );
+    }
+
+    /// Examples from RFC 9110 section 8.8.3.2
+    #[test]
+    fn weak_vs_strong_comparison() {
+        let w1 = EntityTag::weak("1").unwrap();
+        let w2 = EntityTag::weak("2").unwrap();
+        let s1 = EntityTag::strong("1").unwrap();
+
+        assert!(!w1.strong_eq(&w1));
+        assert!(w1.weak_eq(&w1));
+
+        assert!(!w1.strong_eq(&w2));
+        assert!(!w1.weak_eq(&w2));
+
+        assert!(!w1.strong_eq(&s1));
+        assert!(w1.weak_eq(&s1));
+
+        assert!(s1.strong_eq(&s1));
+        assert!(s1.weak_eq(&s1));
+    }
+
+    #[test]
+    fn into_weak_keeps_opaque_tag() {
+        let weak = EntityTag::strong("abc").unwrap().into_weak();
+        assert_eq!(weak, EntityTag::weak("abc").unwrap());
+    }
+}
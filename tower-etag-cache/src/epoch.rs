@@ -0,0 +1,119 @@
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+use crate::{EntityTag, EntityTagError};
+
+/// A deployment epoch or build id prefixed to every ETag generated by a built-in
+/// [`CacheProvider`](crate::CacheProvider), e.g. `"build-42-<hash>"`.
+///
+/// Changing it with [`Self::set`] atomically invalidates every ETag clients have stored,
+/// and the provider treats all entries saved in an earlier epoch as misses.
+///
+/// Reads are lock-free, so checking the epoch on every lookup and put adds no contention.
+///
+/// Cheap to clone, all clones share the same value.
+#[derive(Clone, Debug, Default)]
+pub struct Epoch {
+    current: Arc<ArcSwap<EpochValue>>,
+}
+
+/// The value of an [`Epoch`] at a point in time
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EpochValue {
+    generation: u64,
+    value: Option<Arc<str>>,
+}
+
+impl Epoch {
+    /// Starts at generation 0 with `value`, `None` to not prefix ETags,
+    /// e.g. to prefix ETags with the build id from the first response on.
+    ///
+    /// Errors if `value` contains characters that are not allowed in an entity tag.
+    pub fn new(value: Option<&str>) -> Result<Self, EntityTagError> {
+        Ok(Self {
+            current: Arc::new(ArcSwap::from_pointee(EpochValue {
+                generation: 0,
+                value: validate(value)?,
+            })),
+        })
+    }
+
+    /// Starts a new epoch, `None` to stop prefixing ETags.
+    ///
+    /// Errors if `value` contains characters that are not allowed in an entity tag.
+    pub fn set(&self, value: Option<&str>) -> Result<(), EntityTagError> {
+        let value = validate(value)?;
+        // retried if another set() races this one, so that every call starts a new generation
+        self.current.rcu(|current| EpochValue {
+            generation: current.generation.wrapping_add(1),
+            value: value.clone(),
+        });
+        Ok(())
+    }
+
+    /// Snapshot of the current epoch
+    pub fn get(&self) -> EpochValue {
+        EpochValue::clone(&self.current.load())
+    }
+
+    /// Number of times [`Self::set`] has been called, which identifies the current epoch
+    pub fn generation(&self) -> u64 {
+        self.current.load().generation
+    }
+}
+
+fn validate(value: Option<&str>) -> Result<Option<Arc<str>>, EntityTagError> {
+    if let Some(v) = value {
+        EntityTag::strong(v)?;
+    }
+    Ok(value.map(Arc::from))
+}
+
+impl EpochValue {
+    /// Number of times [`Epoch::set`] had been called, which identifies this epoch
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The ETag prefix, `None` if not set
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// Prefixes `etag`'s opaque-tag with this epoch's value followed by a `-`, if any
+    pub fn apply(&self, etag: EntityTag) -> EntityTag {
+        match &self.value {
+            // unwrap-safety: value was validated by Epoch::new() or Epoch::set()
+            Some(value) => {
+                EntityTag::new(etag.is_weak(), format!("{value}-{}", etag.opaque_tag())).unwrap()
+            }
+            None => etag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_starts_at_generation_zero() {
+        let epoch = Epoch::new(Some("build-42")).unwrap();
+        assert_eq!(epoch.generation(), 0);
+        let etag = epoch.get().apply(EntityTag::strong("abc").unwrap());
+        assert_eq!(etag.opaque_tag(), "build-42-abc");
+        assert!(Epoch::new(Some("\"")).is_err());
+    }
+
+    #[test]
+    fn set_shared_by_clones() {
+        let epoch = Epoch::default();
+        let clone = epoch.clone();
+        clone.set(Some("b")).unwrap();
+        clone.set(None).unwrap();
+        assert_eq!(epoch.generation(), 2);
+        assert_eq!(epoch.get().value(), None);
+        assert!(epoch.set(Some("bad\"")).is_err());
+        assert_eq!(epoch.generation(), 2);
+    }
+}
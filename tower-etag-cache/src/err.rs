@@ -5,21 +5,28 @@ use std::{
 
 /// Error type of the [`EtagCache`](crate::EtagCache) `Service`
 #[derive(Debug)]
-pub enum EtagCacheServiceError<CacheGetError, InnerError, CachePutError> {
+pub enum EtagCacheServiceError<CacheGetError, InnerError, CachePutError, CacheInvalidateError> {
     CacheGetError(CacheGetError),
     InnerError(InnerError),
     CachePutError(CachePutError),
+    CacheInvalidateError(CacheInvalidateError),
     ResponseError(http::Error),
 }
 
-impl<CacheGetError: Display, InnerError: Display, CachePutError: Display> Display
-    for EtagCacheServiceError<CacheGetError, InnerError, CachePutError>
+impl<
+        CacheGetError: Display,
+        InnerError: Display,
+        CachePutError: Display,
+        CacheInvalidateError: Display,
+    > Display
+    for EtagCacheServiceError<CacheGetError, InnerError, CachePutError, CacheInvalidateError>
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::CacheGetError(e) => e.fmt(f),
             Self::InnerError(e) => e.fmt(f),
             Self::CachePutError(e) => e.fmt(f),
+            Self::CacheInvalidateError(e) => e.fmt(f),
             Self::ResponseError(e) => std::fmt::Display::fmt(&e, f),
         }
     }
@@ -29,6 +36,8 @@ impl<
         CacheGetError: Display + Debug,
         InnerError: Display + Debug,
         CachePutError: Display + Debug,
-    > Error for EtagCacheServiceError<CacheGetError, InnerError, CachePutError>
+        CacheInvalidateError: Display + Debug,
+    > Error
+    for EtagCacheServiceError<CacheGetError, InnerError, CachePutError, CacheInvalidateError>
 {
 }
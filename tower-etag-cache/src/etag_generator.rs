@@ -0,0 +1,28 @@
+use crate::EntityTag;
+
+/// Generates the [`EntityTag`] of a response from its head and body bytes.
+///
+/// Built-in [`CacheProvider`](crate::CacheProvider)s are generic over this so that the
+/// hash function can be swapped out. Bodies may be fed to the [`EtagHasher`] in chunks as they are streamed.
+pub trait EtagGenerator {
+    type Hasher: EtagHasher;
+
+    /// Creates a hasher for a single response body
+    fn hasher(&self, parts: &http::response::Parts) -> Self::Hasher;
+
+    /// Generates the entity tag of a fully buffered body
+    fn generate(&self, parts: &http::response::Parts, body: &[u8]) -> EntityTag {
+        let mut hasher = self.hasher(parts);
+        hasher.update(body);
+        hasher.finish()
+    }
+}
+
+/// Incrementally calculates an [`EntityTag`] from a response body's bytes
+pub trait EtagHasher {
+    fn update(&mut self, data: &[u8]);
+
+    /// Returns the entity tag of all the bytes fed to this hasher so far.
+    /// Called at most once.
+    fn finish(&mut self) -> EntityTag;
+}
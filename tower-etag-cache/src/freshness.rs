@@ -0,0 +1,57 @@
+use http::{
+    header::{CACHE_CONTROL, DATE, EXPIRES},
+    HeaderMap,
+};
+use std::time::{Duration, SystemTime};
+
+/// Calculates a response's freshness lifetime from its headers for a shared cache, as per
+/// [RFC 9111 section 4.2.1](https://www.rfc-editor.org/rfc/rfc9111#section-4.2.1):
+/// 1. `Cache-Control: s-maxage`
+/// 2. `Cache-Control: max-age`
+/// 3. `Expires` minus `Date`, or minus the current time if `Date` is absent.
+///    An invalid `Expires` is treated as already expired.
+///
+/// Returns `None` if none of these are present.
+pub fn freshness_lifetime(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age = None;
+    for directive in headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|s| s.split(','))
+    {
+        let (name, value) = match directive.split_once('=') {
+            Some((n, v)) => (n.trim(), v.trim().trim_matches('"')),
+            None => continue,
+        };
+        let secs = match value.parse() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => continue,
+        };
+        if name.eq_ignore_ascii_case("s-maxage") {
+            return Some(secs);
+        }
+        if name.eq_ignore_ascii_case("max-age") {
+            max_age.get_or_insert(secs);
+        }
+    }
+    if max_age.is_some() {
+        return max_age;
+    }
+
+    let expires = headers.get(EXPIRES)?;
+    let expires = match expires
+        .to_str()
+        .ok()
+        .and_then(|s| httpdate::parse_http_date(s).ok())
+    {
+        Some(t) => t,
+        None => return Some(Duration::ZERO),
+    };
+    let date = headers
+        .get(DATE)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|s| httpdate::parse_http_date(s).ok())
+        .unwrap_or_else(SystemTime::now);
+    Some(expires.duration_since(date).unwrap_or(Duration::ZERO))
+}
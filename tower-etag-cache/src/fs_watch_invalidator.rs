@@ -0,0 +1,215 @@
+//! Invalidates the cache entries of files served from a directory, e.g. by `tower_http::services::ServeDir`,
+//! as soon as they change on disk
+
+use std::{
+    collections::BTreeSet,
+    env,
+    fmt::Write,
+    future::poll_fn,
+    path::{Component, Path, PathBuf},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{sync::mpsc, task::JoinHandle};
+use tower_service::Service;
+
+use crate::CacheInvalidateRequest;
+
+/// Maps the paths of changed files under a directory to the request URIs they are served at,
+/// and sends them to a [`CacheProvider`](crate::CacheProvider)'s cache-invalidation `Service`.
+///
+/// `<root>/css/main.css` maps to `<mount>/css/main.css`,
+/// and `<root>/blog/index.html` to both `<mount>/blog/index.html` and `<mount>/blog/`
+/// unless changed with [`Self::index_file`].
+///
+/// Only these exact URIs are invalidated, so URIs with a query string are not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FsWatchInvalidator {
+    root: PathBuf,
+    mount: String,
+    index_file: Option<String>,
+}
+
+impl FsWatchInvalidator {
+    /// Watches `root`, served at `/`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            mount: String::from("/"),
+            index_file: Some(String::from("index.html")),
+        }
+    }
+
+    /// The URI path prefix `root` is served at, e.g. `/static` for `Router::nest_service("/static", ServeDir::new(root))`
+    pub fn mount(mut self, mount: impl Into<String>) -> Self {
+        let mount = mount.into();
+        self.mount = match mount.starts_with('/') {
+            true => mount,
+            false => format!("/{mount}"),
+        };
+        if !self.mount.ends_with('/') {
+            self.mount.push('/');
+        }
+        self
+    }
+
+    /// The file served for directory URIs ending in `/`, `None` if directories are not served.
+    ///
+    /// Defaults to `index.html`, like `ServeDir::append_index_html_on_directories`.
+    pub fn index_file(mut self, index_file: Option<&str>) -> Self {
+        self.index_file = index_file.map(String::from);
+        self
+    }
+
+    /// Returns the URIs `path` is served at, empty if `path` is not under the root
+    /// or contains non-UTF-8 components.
+    ///
+    /// `path` must be absolute if the root is.
+    pub fn uris_for_path(&self, path: &Path) -> Vec<http::Uri> {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return Vec::new();
+        };
+        let mut uri_path = self.mount.clone();
+        let mut last = None;
+        // start of the last segment in uri_path, where the directory URI ends
+        let mut last_start = uri_path.len();
+        for component in relative.components() {
+            let Component::Normal(segment) = component else {
+                return Vec::new();
+            };
+            let Some(segment) = segment.to_str() else {
+                return Vec::new();
+            };
+            if last.is_some() {
+                uri_path.push('/');
+            }
+            last_start = uri_path.len();
+            percent_encode_segment(&mut uri_path, segment);
+            last = Some(segment);
+        }
+        let Some(file_name) = last else {
+            return Vec::new();
+        };
+        let mut uris = Vec::with_capacity(2);
+        if self.index_file.as_deref() == Some(file_name) {
+            uris.extend(uri_path[..last_start].parse().ok());
+        }
+        uris.extend(uri_path.parse().ok());
+        uris
+    }
+
+    /// Starts watching the root recursively, invalidating the URIs of all created,
+    /// modified and removed files with `invalidate`.
+    ///
+    /// Must be called within a tokio runtime.
+    /// Invalidation errors are ignored, and watching stops once `invalidate` is no longer ready
+    /// or the returned [`FsWatchGuard`] is dropped.
+    pub fn watch<S>(mut self, invalidate: S) -> notify::Result<FsWatchGuard>
+    where
+        S: Service<CacheInvalidateRequest, Response = ()> + Send + 'static,
+        S::Future: Send,
+    {
+        // event paths are absolute
+        if self.root.is_relative() {
+            self.root = env::current_dir()
+                .map_err(notify::Error::io)?
+                .join(&self.root);
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            if let Ok(event) = res {
+                // receiver is only dropped once the watcher is
+                let _ = tx.send(event);
+            }
+        })?;
+        watcher.watch(&self.root, RecursiveMode::Recursive)?;
+        let task = tokio::spawn(self.run(rx, invalidate));
+        Ok(FsWatchGuard {
+            _watcher: watcher,
+            task,
+        })
+    }
+
+    async fn run<S>(self, mut rx: mpsc::UnboundedReceiver<Event>, mut invalidate: S)
+    where
+        S: Service<CacheInvalidateRequest, Response = ()>,
+    {
+        while let Some(event) = rx.recv().await {
+            let mut uris = BTreeSet::new();
+            self.add_uris(&mut uris, event);
+            // editors often write a file in several steps, handle them all at once
+            while let Ok(event) = rx.try_recv() {
+                self.add_uris(&mut uris, event);
+            }
+            if uris.is_empty() {
+                continue;
+            }
+            if poll_fn(|cx| invalidate.poll_ready(cx)).await.is_err() {
+                return;
+            }
+            let uris = uris.into_iter().filter_map(|s| s.parse().ok()).collect();
+            let _ = invalidate.call(CacheInvalidateRequest { uris }).await;
+        }
+    }
+
+    /// http::Uri is not Ord, so the URIs are collected as strings
+    fn add_uris(&self, uris: &mut BTreeSet<String>, event: Event) {
+        if !matches!(
+            event.kind,
+            EventKind::Any | EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            return;
+        }
+        for path in event.paths {
+            uris.extend(self.uris_for_path(&path).iter().map(|uri| uri.to_string()));
+        }
+    }
+}
+
+/// Keeps the root of a [`FsWatchInvalidator`] watched until dropped
+#[derive(Debug)]
+pub struct FsWatchGuard {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl Drop for FsWatchGuard {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Appends `segment` to `uri_path`, percent-encoding all characters not allowed in a URI path segment
+fn percent_encode_segment(uri_path: &mut String, segment: &str) {
+    for c in segment.chars() {
+        match c {
+            'A'..='Z'
+            | 'a'..='z'
+            | '0'..='9'
+            | '-'
+            | '.'
+            | '_'
+            | '~'
+            | '!'
+            | '$'
+            | '&'
+            | '\''
+            | '('
+            | ')'
+            | '*'
+            | '+'
+            | ','
+            | ';'
+            | '='
+            | ':'
+            | '@' => uri_path.push(c),
+            _ => {
+                let mut buf = [0; 4];
+                for b in c.encode_utf8(&mut buf).bytes() {
+                    // unwrap-safety: writing to a String never fails
+                    write!(uri_path, "%{b:02X}").unwrap();
+                }
+            }
+        }
+    }
+}
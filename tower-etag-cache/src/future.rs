@@ -1,3 +1,4 @@
+use http::{HeaderName, Method};
 use pin_project::pin_project;
 use std::{
     future::Future,
@@ -8,8 +9,11 @@ use std::{
 use tower_service::Service;
 
 use crate::{
-    cache_provider::CacheProvider, CacheGetResponse, CacheGetResponseResult, EtagCacheResBody,
-    EtagCacheServiceError, PassthroughPredicate,
+    cache_provider::CacheProvider, invalidation::InvalidationTarget, refresh::into_refresh_request,
+    CacheGetResponse, CacheGetResponseResult, CacheInvalidateRequest, CacheTags, DiscardedBody,
+    EtagCacheResBody, EtagCacheServiceError, HeadResponse, NoRefresh, PassthroughPredicate,
+    PreconditionCheck, RefreshSpawner, RequestCredentials, SingleFlightGuard, SingleFlightRequest,
+    StaleEntry, WeakEtag,
 };
 
 /// `Future` struct returned by [`EtagCache::call`](crate::EtagCache::call)
@@ -20,10 +24,25 @@ pub struct EtagCacheServiceFuture<
     C: CacheProvider<ReqBody, ResBody>,
     P: PassthroughPredicate,
     S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    R = NoRefresh,
 > {
     cache_provider: C,
     passthrough_predicate: P,
     inner: S,
+    /// true if the request only runs through the cache-lookup service
+    /// to evaluate its preconditions and its response should not be cached
+    precondition_only: bool,
+    /// Some if the request has an unsafe method and the cache should be invalidated if it succeeds
+    invalidation: Option<InvalidationTarget>,
+    /// true if the request is a cacheable `HEAD` request
+    head: bool,
+    /// The request's credentials, inserted into its response as a [`RequestCredentials`] if any
+    credentials: RequestCredentials,
+    /// Passed to the cache-put service with the response, if the cache provider made other requests wait on this one
+    single_flight_guard: Option<SingleFlightGuard>,
+    options: Options,
+    /// Runs the refresh of the cache entry if the cache provider marks it as stale
+    refresh_spawner: R,
     #[pin]
     state: EtagCacheServiceFutureState<ReqBody, ResBody, C, S>,
 }
@@ -46,12 +65,33 @@ impl<
             cache_provider,
             passthrough_predicate,
             inner,
+            precondition_only: false,
+            invalidation: InvalidationTarget::for_req(&req),
+            head: req.method() == Method::HEAD,
+            credentials: RequestCredentials::from_headers(req.headers()),
+            single_flight_guard: None,
+            options: Options::default(),
+            refresh_spawner: NoRefresh,
             state: EtagCacheServiceFutureState::CacheGetBefore {
                 req: ManuallyDrop::new(req),
             },
         }
     }
 
+    /// Runs the request through the cache-lookup service to evaluate its preconditions,
+    /// but passes the response through without caching it
+    pub fn preconditions(
+        cache_provider: C,
+        passthrough_predicate: P,
+        inner: S,
+        req: http::Request<ReqBody>,
+    ) -> Self {
+        Self {
+            precondition_only: true,
+            ..Self::start(cache_provider, passthrough_predicate, inner, req)
+        }
+    }
+
     pub fn passthrough(
         cache_provider: C,
         passthrough_predicate: P,
@@ -62,6 +102,13 @@ impl<
             cache_provider,
             passthrough_predicate,
             inner,
+            precondition_only: false,
+            invalidation: InvalidationTarget::for_req(&req),
+            head: false,
+            credentials: RequestCredentials::default(),
+            single_flight_guard: None,
+            options: Options::default(),
+            refresh_spawner: NoRefresh,
             state: EtagCacheServiceFutureState::InnerBefore {
                 key: None,
                 req: ManuallyDrop::new(req),
@@ -70,6 +117,60 @@ impl<
     }
 }
 
+impl<
+        ReqBody,
+        ResBody,
+        C: CacheProvider<ReqBody, ResBody>,
+        P: PassthroughPredicate,
+        S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+        R,
+    > EtagCacheServiceFuture<ReqBody, ResBody, C, P, S, R>
+{
+    /// See [`EtagCache::head_fetches_get`](crate::EtagCache::head_fetches_get)
+    pub fn head_fetches_get(mut self, head_fetches_get: bool) -> Self {
+        self.options.head_fetches_get = head_fetches_get;
+        self
+    }
+
+    /// See [`EtagCache::single_flight`](crate::EtagCache::single_flight)
+    pub fn single_flight(mut self, single_flight: bool) -> Self {
+        self.options.single_flight = single_flight;
+        self
+    }
+
+    /// See [`EtagCache::tag_header`](crate::EtagCache::tag_header)
+    pub fn tag_header(mut self, tag_header: Option<HeaderName>) -> Self {
+        self.options.tag_header = tag_header;
+        self
+    }
+
+    /// See [`EtagCache::strip_tag_header`](crate::EtagCache::strip_tag_header)
+    pub fn strip_tag_header(mut self, strip_tag_header: bool) -> Self {
+        self.options.strip_tag_header = strip_tag_header;
+        self
+    }
+
+    /// See [`EtagCache::refresh_with`](crate::EtagCache::refresh_with)
+    pub fn refresh_with<R2>(
+        self,
+        refresh_spawner: R2,
+    ) -> EtagCacheServiceFuture<ReqBody, ResBody, C, P, S, R2> {
+        EtagCacheServiceFuture {
+            cache_provider: self.cache_provider,
+            passthrough_predicate: self.passthrough_predicate,
+            inner: self.inner,
+            precondition_only: self.precondition_only,
+            invalidation: self.invalidation,
+            head: self.head,
+            credentials: self.credentials,
+            single_flight_guard: self.single_flight_guard,
+            options: self.options,
+            refresh_spawner,
+            state: self.state,
+        }
+    }
+}
+
 // Use ManuallyDrop to allow easy moving of fields behind Pin<&mut self> to the next state
 /// `Enum` used to build the [`EtagCache`](crate::EtagCache) `Service`'s state machine
 #[pin_project(project = EtagCacheServiceFutureStateProj)]
@@ -105,15 +206,26 @@ enum EtagCacheServiceFutureState<
         #[pin]
         fut: <C as Service<(C::Key, http::Response<ResBody>)>>::Future,
     },
+    CacheInvalidateBefore {
+        invalidate_req: ManuallyDrop<CacheInvalidateRequest>,
+        resp: ManuallyDrop<http::Response<ResBody>>,
+    },
+    CacheInvalidate {
+        #[pin]
+        fut: <C as Service<CacheInvalidateRequest>>::Future,
+        /// Returned once invalidation completes
+        resp: Option<http::Response<ResBody>>,
+    },
 }
 
 impl<
         ReqBody,
         ResBody,
-        C: CacheProvider<ReqBody, ResBody>,
+        C: CacheProvider<ReqBody, ResBody> + Clone,
         P: PassthroughPredicate,
-        S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
-    > Future for EtagCacheServiceFuture<ReqBody, ResBody, C, P, S>
+        S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone,
+        R: RefreshSpawner<EtagCacheServiceFuture<ReqBody, ResBody, C, P, S>>,
+    > Future for EtagCacheServiceFuture<ReqBody, ResBody, C, P, S, R>
 {
     type Output = Result<
         http::Response<EtagCacheResBody<ResBody, C::TResBody>>,
@@ -121,6 +233,7 @@ impl<
             <C as Service<http::Request<ReqBody>>>::Error,
             S::Error,
             <C as Service<(C::Key, http::Response<ResBody>)>>::Error,
+            <C as Service<CacheInvalidateRequest>>::Error,
         >,
     >;
 
@@ -136,10 +249,15 @@ impl<
                         if let Err(e) = result {
                             return Poll::Ready(Err(EtagCacheServiceError::CacheGetError(e)));
                         }
-                        let fut = <C as Service<http::Request<ReqBody>>>::call(
-                            this.cache_provider,
-                            unsafe { ManuallyDrop::take(req) },
-                        );
+                        let mut req = unsafe { ManuallyDrop::take(req) };
+                        if this.options.single_flight {
+                            req.extensions_mut().insert(SingleFlightRequest);
+                        }
+                        if *this.precondition_only {
+                            req.extensions_mut().insert(PreconditionCheck);
+                        }
+                        let fut =
+                            <C as Service<http::Request<ReqBody>>>::call(this.cache_provider, req);
                         curr_state.set(EtagCacheServiceFutureState::CacheGet { fut });
                         cx.waker().wake_by_ref();
                         Poll::Pending
@@ -149,21 +267,60 @@ impl<
             EtagCacheServiceFutureStateProj::CacheGet { fut } => match fut.poll(cx) {
                 Poll::Pending => Poll::Pending,
                 Poll::Ready(result) => {
-                    let CacheGetResponse { req, result } = match result {
+                    let CacheGetResponse { mut req, result } = match result {
                         Ok(r) => r,
                         Err(e) => return Poll::Ready(Err(EtagCacheServiceError::CacheGetError(e))),
                     };
+                    let stale = req.extensions_mut().remove::<StaleEntry>().is_some();
+                    req.extensions_mut().remove::<SingleFlightRequest>();
+                    req.extensions_mut().remove::<PreconditionCheck>();
+                    *this.single_flight_guard = req.extensions_mut().remove::<SingleFlightGuard>();
                     let key = match result {
-                        CacheGetResponseResult::Hit(headers) => {
+                        CacheGetResponseResult::Hit(etag, headers) => {
+                            if stale {
+                                spawn_refresh(
+                                    this.refresh_spawner,
+                                    this.cache_provider,
+                                    this.passthrough_predicate,
+                                    this.inner,
+                                    this.options,
+                                    req,
+                                );
+                            }
+                            return Poll::Ready(
+                                EtagCacheResBody::hit_resp(&etag, headers)
+                                    .map_err(EtagCacheServiceError::ResponseError),
+                            );
+                        }
+                        CacheGetResponseResult::PreconditionFailed(headers) => {
+                            return Poll::Ready(
+                                EtagCacheResBody::precondition_failed_resp(headers)
+                                    .map_err(EtagCacheServiceError::ResponseError),
+                            );
+                        }
+                        CacheGetResponseResult::Full(etag, cached) => {
+                            if stale {
+                                spawn_refresh(
+                                    this.refresh_spawner,
+                                    this.cache_provider,
+                                    this.passthrough_predicate,
+                                    this.inner,
+                                    this.options,
+                                    req,
+                                );
+                            }
                             return Poll::Ready(
-                                EtagCacheResBody::hit_resp(headers)
+                                EtagCacheResBody::full_resp(&etag, cached, *this.head)
                                     .map_err(EtagCacheServiceError::ResponseError),
                             );
                         }
                         CacheGetResponseResult::Miss(k) => k,
                     };
+                    if *this.head && this.options.head_fetches_get && !*this.precondition_only {
+                        *req.method_mut() = Method::GET;
+                    }
                     curr_state.set(EtagCacheServiceFutureState::InnerBefore {
-                        key: Some(key),
+                        key: (!*this.precondition_only).then_some(key),
                         req: ManuallyDrop::new(req),
                     });
                     cx.waker().wake_by_ref();
@@ -188,19 +345,57 @@ impl<
             EtagCacheServiceFutureStateProj::Inner { key, fut } => match fut.poll(cx) {
                 Poll::Pending => Poll::Pending,
                 Poll::Ready(result) => {
-                    let resp = match result {
+                    let mut resp = match result {
                         Ok(r) => r,
                         Err(e) => return Poll::Ready(Err(EtagCacheServiceError::InnerError(e))),
                     };
 
+                    if let Some(name) = &this.options.tag_header {
+                        add_header_tags(&mut resp, name, this.options.strip_tag_header);
+                    }
+
+                    if resp.status().is_success() {
+                        if let Some(target) = this.invalidation.take() {
+                            curr_state.set(EtagCacheServiceFutureState::CacheInvalidateBefore {
+                                invalidate_req: ManuallyDrop::new(
+                                    target.into_invalidate_request(resp.headers()),
+                                ),
+                                resp: ManuallyDrop::new(resp),
+                            });
+                            cx.waker().wake_by_ref();
+                            return Poll::Pending;
+                        }
+                    }
+
                     if this.passthrough_predicate.should_passthrough_resp(&resp) {
                         return Poll::Ready(Ok(EtagCacheResBody::passthrough_resp(resp)));
                     }
 
+                    if this.passthrough_predicate.should_weaken_etag(&resp) {
+                        resp.extensions_mut().insert(WeakEtag);
+                    }
+
+                    match (*this.head, this.options.head_fetches_get) {
+                        (true, false) => {
+                            resp.extensions_mut().insert(HeadResponse);
+                        }
+                        (true, true) => {
+                            resp.extensions_mut().insert(DiscardedBody);
+                        }
+                        (false, _) => (),
+                    }
+
+                    if !this.credentials.is_empty() {
+                        resp.extensions_mut().insert(*this.credentials);
+                    }
+
                     let k = match key.take() {
                         Some(k) => k,
                         None => return Poll::Ready(Ok(EtagCacheResBody::passthrough_resp(resp))),
                     };
+                    if let Some(guard) = this.single_flight_guard.take() {
+                        resp.extensions_mut().insert(guard);
+                    }
                     curr_state.set(EtagCacheServiceFutureState::CachePutBefore {
                         key: ManuallyDrop::new(k),
                         resp: ManuallyDrop::new(resp),
@@ -233,12 +428,110 @@ impl<
             }
             EtagCacheServiceFutureStateProj::CachePut { fut } => match fut.poll(cx) {
                 Poll::Pending => Poll::Pending,
-                Poll::Ready(result) => Poll::Ready(
-                    result
-                        .map(EtagCacheResBody::miss_resp)
-                        .map_err(EtagCacheServiceError::CachePutError),
-                ),
+                Poll::Ready(result) => {
+                    let mut resp = match result {
+                        Ok(r) => r,
+                        Err(e) => return Poll::Ready(Err(EtagCacheServiceError::CachePutError(e))),
+                    };
+                    // released here if the cache provider did not take it
+                    resp.extensions_mut().remove::<SingleFlightGuard>();
+                    // the GET representation was fetched for a HEAD request
+                    Poll::Ready(Ok(match *this.head && this.options.head_fetches_get {
+                        true => EtagCacheResBody::head_resp(resp),
+                        false => EtagCacheResBody::miss_resp(resp),
+                    }))
+                }
+            },
+            EtagCacheServiceFutureStateProj::CacheInvalidateBefore {
+                invalidate_req,
+                resp,
+            } => {
+                match <C as Service<CacheInvalidateRequest>>::poll_ready(this.cache_provider, cx) {
+                    Poll::Pending => Poll::Pending,
+                    Poll::Ready(result) => {
+                        if let Err(e) = result {
+                            return Poll::Ready(Err(EtagCacheServiceError::CacheInvalidateError(
+                                e,
+                            )));
+                        }
+                        let fut = <C as Service<CacheInvalidateRequest>>::call(
+                            this.cache_provider,
+                            unsafe { ManuallyDrop::take(invalidate_req) },
+                        );
+                        let resp = Some(unsafe { ManuallyDrop::take(resp) });
+                        curr_state.set(EtagCacheServiceFutureState::CacheInvalidate { fut, resp });
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                }
+            }
+            EtagCacheServiceFutureStateProj::CacheInvalidate { fut, resp } => match fut.poll(cx) {
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(result) => {
+                    if let Err(e) = result {
+                        return Poll::Ready(Err(EtagCacheServiceError::CacheInvalidateError(e)));
+                    }
+                    // unwrap-safety: only taken once here upon completion
+                    let resp = resp.take().unwrap();
+                    Poll::Ready(Ok(EtagCacheResBody::passthrough_resp(resp)))
+                }
             },
         }
     }
 }
+
+/// Settings of the [`EtagCache`](crate::EtagCache) that created the future,
+/// carried over to the refreshes it spawns
+#[derive(Clone, Debug, Default)]
+struct Options {
+    /// true if a `HEAD` request that misses the cache is sent to the inner service as a `GET` request
+    head_fetches_get: bool,
+    /// true if the request should be marked as a [`SingleFlightRequest`]
+    single_flight: bool,
+    /// Response header whose values are added to the response's [`CacheTags`]
+    tag_header: Option<HeaderName>,
+    /// true if `tag_header` should be removed from the response
+    strip_tag_header: bool,
+}
+
+/// Runs `req`, which was answered with a stale cache entry, through the cache again with `refresh_spawner`,
+/// with the same [`Options`]
+fn spawn_refresh<ReqBody, ResBody, C, P, S, R>(
+    refresh_spawner: &R,
+    cache_provider: &C,
+    passthrough_predicate: &P,
+    inner: &S,
+    options: &Options,
+    req: http::Request<ReqBody>,
+) where
+    C: CacheProvider<ReqBody, ResBody> + Clone,
+    P: PassthroughPredicate,
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone,
+    R: RefreshSpawner<EtagCacheServiceFuture<ReqBody, ResBody, C, P, S>>,
+{
+    refresh_spawner.spawn(EtagCacheServiceFuture {
+        options: options.clone(),
+        ..EtagCacheServiceFuture::start(
+            cache_provider.clone(),
+            passthrough_predicate.clone(),
+            inner.clone(),
+            into_refresh_request(req),
+        )
+    });
+}
+
+/// Adds the tags in the `name` header of `resp` to its [`CacheTags`], removing the header if `strip`
+fn add_header_tags<T>(resp: &mut http::Response<T>, name: &HeaderName, strip: bool) {
+    if !resp.headers().contains_key(name) {
+        return;
+    }
+    let mut tags = resp
+        .extensions_mut()
+        .remove::<CacheTags>()
+        .unwrap_or_default();
+    tags.extend_from_header(resp.headers(), name);
+    resp.extensions_mut().insert(tags);
+    if strip {
+        resp.headers_mut().remove(name);
+    }
+}
@@ -0,0 +1,81 @@
+use http::{
+    header::{CONTENT_LOCATION, HOST, LOCATION},
+    uri::Authority,
+    HeaderMap, Uri,
+};
+
+use crate::{is_safe_method, CacheInvalidateRequest};
+
+/// The target of a request with an unsafe method whose cache entries must be invalidated
+/// if it succeeds, as per [RFC 9111 section 4.4](https://www.rfc-editor.org/rfc/rfc9111#section-4.4)
+#[derive(Debug, Clone)]
+pub(crate) struct InvalidationTarget {
+    uri: Uri,
+    /// Used to only invalidate `Location` and `Content-Location` URIs with the same origin
+    authority: Option<Authority>,
+}
+
+impl InvalidationTarget {
+    /// Returns `None` if `req` has a safe method
+    pub fn for_req<T>(req: &http::Request<T>) -> Option<Self> {
+        if is_safe_method(req.method()) {
+            return None;
+        }
+        let authority = req.uri().authority().cloned().or_else(|| {
+            req.headers()
+                .get(HOST)
+                .and_then(|hv| hv.to_str().ok())
+                .and_then(|s| s.parse().ok())
+        });
+        Some(Self {
+            uri: req.uri().clone(),
+            authority,
+        })
+    }
+
+    /// Invalidates the request's target URI and
+    /// the response's `Location` and `Content-Location` URIs if they have the same origin
+    pub fn into_invalidate_request(self, resp_headers: &HeaderMap) -> CacheInvalidateRequest {
+        let mut uris = Vec::with_capacity(3);
+        for name in [LOCATION, CONTENT_LOCATION] {
+            let uri = resp_headers
+                .get(name)
+                .and_then(|hv| hv.to_str().ok())
+                .and_then(|s| self.resolve(s));
+            if let Some(uri) = uri {
+                if !uris.contains(&uri) && uri != self.uri {
+                    uris.push(uri);
+                }
+            }
+        }
+        uris.insert(0, self.uri);
+        CacheInvalidateRequest { uris }
+    }
+
+    /// Resolves a `Location` or `Content-Location` URI-reference against the request URI,
+    /// returning it in the same form as the request URI.
+    ///
+    /// Returns `None` if the reference is invalid or has a different authority.
+    fn resolve(&self, reference: &str) -> Option<Uri> {
+        if reference.starts_with("//") || reference.contains("://") {
+            let absolute: Uri = reference.parse().ok()?;
+            if absolute.authority() != self.authority.as_ref() {
+                return None;
+            }
+            return self.with_path_and_query(absolute.path_and_query()?.as_str());
+        }
+        if reference.starts_with('/') {
+            return self.with_path_and_query(reference);
+        }
+        // relative path: replace the last segment of the request path
+        let base_path = self.uri.path();
+        let dir = &base_path[..=base_path.rfind('/')?];
+        self.with_path_and_query(&format!("{dir}{reference}"))
+    }
+
+    fn with_path_and_query(&self, path_and_query: &str) -> Option<Uri> {
+        let mut parts = self.uri.clone().into_parts();
+        parts.path_and_query = Some(path_and_query.parse().ok()?);
+        Uri::from_parts(parts).ok()
+    }
+}
@@ -1,40 +1,102 @@
 #![doc = include_str!("../README.md")]
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+use http::{HeaderName, Method};
 use std::task::Poll;
 use tower_layer::Layer;
 use tower_service::Service;
 
+mod cache_key_extractor;
 mod cache_provider;
+mod entity_tag;
+mod epoch;
 mod err;
+mod etag_generator;
+mod freshness;
 mod future;
+mod invalidation;
 mod passthrough_predicate;
+#[cfg(any(feature = "const-lru-provider", feature = "shared-lru-provider"))]
+mod provider_util;
+mod purge;
+mod refresh;
 mod response;
+mod single_flight;
+mod tags;
+mod validators;
 
 #[cfg(feature = "simple-etag-cache-key")]
 #[cfg_attr(docsrs, doc(cfg(feature = "simple-etag-cache-key")))]
 pub mod simple_etag_cache_key;
 
+#[cfg(feature = "configurable-etag-cache-key")]
+#[cfg_attr(docsrs, doc(cfg(feature = "configurable-etag-cache-key")))]
+pub mod configurable_etag_cache_key;
+
 #[cfg(feature = "base64-blake3-body-etag")]
 #[cfg_attr(docsrs, doc(cfg(feature = "base64-blake3-body-etag")))]
 pub mod base64_blake3_body_etag;
 
+#[cfg(feature = "sha256-etag")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sha256-etag")))]
+pub mod sha256_etag;
+
+#[cfg(feature = "xxh3-etag")]
+#[cfg_attr(docsrs, doc(cfg(feature = "xxh3-etag")))]
+pub mod xxh3_etag;
+
+#[cfg(feature = "crc32-etag")]
+#[cfg_attr(docsrs, doc(cfg(feature = "crc32-etag")))]
+pub mod crc32_etag;
+
+#[cfg(feature = "version-etag")]
+#[cfg_attr(docsrs, doc(cfg(feature = "version-etag")))]
+pub mod version_etag;
+
+#[cfg(feature = "tokio-refresh-spawner")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio-refresh-spawner")))]
+pub mod tokio_refresh_spawner;
+
 #[cfg(feature = "const-lru-provider")]
 #[cfg_attr(docsrs, doc(cfg(feature = "const-lru-provider")))]
 pub mod const_lru_provider;
 
+#[cfg(feature = "shared-lru-provider")]
+#[cfg_attr(docsrs, doc(cfg(feature = "shared-lru-provider")))]
+pub mod shared_lru_provider;
+
+#[cfg(feature = "fs-watch-invalidator")]
+#[cfg_attr(docsrs, doc(cfg(feature = "fs-watch-invalidator")))]
+pub mod fs_watch_invalidator;
+
+pub use cache_key_extractor::*;
 pub use cache_provider::*;
+pub use entity_tag::*;
+pub use epoch::*;
 pub use err::*;
+pub use etag_generator::*;
+pub use freshness::*;
 pub use future::*;
 pub use passthrough_predicate::*;
+pub use purge::*;
+pub use refresh::*;
 pub use response::*;
+pub use single_flight::*;
+pub use tags::*;
+pub use validators::*;
 
 /// The eponymous tower `Service`
-#[derive(Clone, Copy, Debug)]
-pub struct EtagCache<C, P, S> {
+#[derive(Clone, Debug)]
+pub struct EtagCache<C, P, S, R = NoRefresh> {
     cache_provider: C,
     passthrough_predicate: P,
     inner: S,
+    enforce_preconditions: bool,
+    head_fetches_get: bool,
+    single_flight: bool,
+    tag_header: Option<HeaderName>,
+    strip_tag_header: bool,
+    refresh_spawner: R,
 }
 
 impl<C, P, S> EtagCache<C, P, S> {
@@ -43,25 +105,115 @@ impl<C, P, S> EtagCache<C, P, S> {
             cache_provider,
             passthrough_predicate,
             inner,
+            enforce_preconditions: false,
+            head_fetches_get: false,
+            single_flight: false,
+            tag_header: None,
+            strip_tag_header: false,
+            refresh_spawner: NoRefresh,
+        }
+    }
+}
+
+impl<C, P, S, R> EtagCache<C, P, S, R> {
+    /// If true, requests with unsafe methods (e.g. `PUT`, `PATCH`, `DELETE`) that are passed through
+    /// and have conditional headers are first run through the cache-lookup service.
+    /// The request is short-circuited with HTTP 412 Precondition Failed
+    /// if the cached ETags or last-modified times fail its `If-Match`, `If-Unmodified-Since` or `If-None-Match`.
+    ///
+    /// The preconditions are evaluated against every cached representation of the request's target URI,
+    /// regardless of the request headers the cache key varies on, see [`PreconditionCheck`].
+    /// `If-Match` passes if any of their ETags matches.
+    /// If none is cached, e.g. because the resource was never fetched or its entry was evicted,
+    /// the request is passed to the inner service, which must then evaluate the preconditions itself.
+    ///
+    /// Defaults to false.
+    pub fn enforce_preconditions(mut self, enforce_preconditions: bool) -> Self {
+        self.enforce_preconditions = enforce_preconditions;
+        self
+    }
+
+    /// If true, `HEAD` requests that miss the cache are sent to the inner service as `GET` requests
+    /// so that the ETag of the `GET` representation can be calculated and saved.
+    /// The response body is then discarded, see [`EtagCacheResBody::Head`],
+    /// and the response is marked with the [`DiscardedBody`] extension.
+    ///
+    /// If false, `HEAD` responses are marked with the [`HeadResponse`] extension
+    /// and only carry the ETag already saved by a previous `GET` request, if any.
+    ///
+    /// Defaults to false.
+    pub fn head_fetches_get(mut self, head_fetches_get: bool) -> Self {
+        self.head_fetches_get = head_fetches_get;
+        self
+    }
+
+    /// If true, requests that run through the cache are marked as [`SingleFlightRequest`]s
+    /// so that the [`CacheProvider`] can make concurrent misses for the same cache key wait on the first one.
+    /// The [`SingleFlightGuard`] of the first miss is passed to the cache-put service with its response
+    /// and held until the response has been cached,
+    /// after which the waiting requests look up the cache again and share its ETag or saved response.
+    ///
+    /// Defaults to false.
+    pub fn single_flight(mut self, single_flight: bool) -> Self {
+        self.single_flight = single_flight;
+        self
+    }
+
+    /// Adds the tags in the values of the `tag_header` response header, e.g. `Surrogate-Key` or `Cache-Tag`,
+    /// to the response's [`CacheTags`] so that the [`CacheProvider`] indexes its cache entry by them.
+    /// Values are split on whitespace and commas.
+    ///
+    /// Defaults to `None`.
+    pub fn tag_header(mut self, tag_header: Option<HeaderName>) -> Self {
+        self.tag_header = tag_header;
+        self
+    }
+
+    /// If true, the [`Self::tag_header`] is removed from responses before they leave this service.
+    ///
+    /// Defaults to false.
+    pub fn strip_tag_header(mut self, strip_tag_header: bool) -> Self {
+        self.strip_tag_header = strip_tag_header;
+        self
+    }
+
+    /// Refreshes the cache entries that the [`CacheProvider`] marks as [`StaleEntry`] in the background,
+    /// by running a [`RefreshRequest`] through this service with `refresh_spawner`.
+    /// The stale entry is still used to respond to the request that triggered the refresh.
+    ///
+    /// Defaults to [`NoRefresh`].
+    pub fn refresh_with<R2>(self, refresh_spawner: R2) -> EtagCache<C, P, S, R2> {
+        EtagCache {
+            cache_provider: self.cache_provider,
+            passthrough_predicate: self.passthrough_predicate,
+            inner: self.inner,
+            enforce_preconditions: self.enforce_preconditions,
+            head_fetches_get: self.head_fetches_get,
+            single_flight: self.single_flight,
+            tag_header: self.tag_header,
+            strip_tag_header: self.strip_tag_header,
+            refresh_spawner,
         }
     }
 }
 
 impl<C, S> EtagCache<C, DefaultPredicate, S> {
     pub fn with_default_predicate(cache_provider: C, inner: S) -> Self {
-        Self {
-            cache_provider,
-            passthrough_predicate: DefaultPredicate,
-            inner,
-        }
+        Self::new(cache_provider, DefaultPredicate, inner)
     }
 }
 
 /// The eponymous tower `Layer`
-#[derive(Clone, Copy, Debug)]
-pub struct EtagCacheLayer<C, P> {
+#[derive(Clone, Debug)]
+pub struct EtagCacheLayer<C, P, R = NoRefresh> {
     cache_provider: C,
     passthrough_predicate: P,
+    enforce_preconditions: bool,
+    head_fetches_get: bool,
+    single_flight: bool,
+    tag_header: Option<HeaderName>,
+    strip_tag_header: bool,
+    refresh_spawner: R,
 }
 
 impl<C, P> EtagCacheLayer<C, P> {
@@ -69,21 +221,70 @@ impl<C, P> EtagCacheLayer<C, P> {
         Self {
             cache_provider,
             passthrough_predicate,
+            enforce_preconditions: false,
+            head_fetches_get: false,
+            single_flight: false,
+            tag_header: None,
+            strip_tag_header: false,
+            refresh_spawner: NoRefresh,
+        }
+    }
+}
+
+impl<C, P, R> EtagCacheLayer<C, P, R> {
+    /// See [`EtagCache::enforce_preconditions`]
+    pub fn enforce_preconditions(mut self, enforce_preconditions: bool) -> Self {
+        self.enforce_preconditions = enforce_preconditions;
+        self
+    }
+
+    /// See [`EtagCache::head_fetches_get`]
+    pub fn head_fetches_get(mut self, head_fetches_get: bool) -> Self {
+        self.head_fetches_get = head_fetches_get;
+        self
+    }
+
+    /// See [`EtagCache::single_flight`]
+    pub fn single_flight(mut self, single_flight: bool) -> Self {
+        self.single_flight = single_flight;
+        self
+    }
+
+    /// See [`EtagCache::tag_header`]
+    pub fn tag_header(mut self, tag_header: Option<HeaderName>) -> Self {
+        self.tag_header = tag_header;
+        self
+    }
+
+    /// See [`EtagCache::strip_tag_header`]
+    pub fn strip_tag_header(mut self, strip_tag_header: bool) -> Self {
+        self.strip_tag_header = strip_tag_header;
+        self
+    }
+
+    /// See [`EtagCache::refresh_with`]
+    pub fn refresh_with<R2>(self, refresh_spawner: R2) -> EtagCacheLayer<C, P, R2> {
+        EtagCacheLayer {
+            cache_provider: self.cache_provider,
+            passthrough_predicate: self.passthrough_predicate,
+            enforce_preconditions: self.enforce_preconditions,
+            head_fetches_get: self.head_fetches_get,
+            single_flight: self.single_flight,
+            tag_header: self.tag_header,
+            strip_tag_header: self.strip_tag_header,
+            refresh_spawner,
         }
     }
 }
 
 impl<C> EtagCacheLayer<C, DefaultPredicate> {
     pub fn with_default_predicate(cache_provider: C) -> Self {
-        Self {
-            cache_provider,
-            passthrough_predicate: DefaultPredicate,
-        }
+        Self::new(cache_provider, DefaultPredicate)
     }
 }
 
-impl<C: Clone, P: Clone, S> Layer<S> for EtagCacheLayer<C, P> {
-    type Service = EtagCache<C, P, S>;
+impl<C: Clone, P: Clone, S, R: Clone> Layer<S> for EtagCacheLayer<C, P, R> {
+    type Service = EtagCache<C, P, S, R>;
 
     fn layer(&self, inner: S) -> Self::Service {
         EtagCache::new(
@@ -91,14 +292,21 @@ impl<C: Clone, P: Clone, S> Layer<S> for EtagCacheLayer<C, P> {
             self.passthrough_predicate.clone(),
             inner,
         )
+        .enforce_preconditions(self.enforce_preconditions)
+        .head_fetches_get(self.head_fetches_get)
+        .single_flight(self.single_flight)
+        .tag_header(self.tag_header.clone())
+        .strip_tag_header(self.strip_tag_header)
+        .refresh_with(self.refresh_spawner.clone())
     }
 }
 
-impl<ReqBody, ResBody, C, P, S> Service<http::Request<ReqBody>> for EtagCache<C, P, S>
+impl<ReqBody, ResBody, C, P, S, R> Service<http::Request<ReqBody>> for EtagCache<C, P, S, R>
 where
     C: CacheProvider<ReqBody, ResBody> + Clone,
     P: PassthroughPredicate,
     S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone,
+    R: RefreshSpawner<EtagCacheServiceFuture<ReqBody, ResBody, C, P, S>> + Clone,
 {
     type Response = http::Response<EtagCacheResBody<ResBody, C::TResBody>>;
 
@@ -106,9 +314,10 @@ where
         <C as Service<http::Request<ReqBody>>>::Error,
         S::Error,
         <C as Service<(C::Key, http::Response<ResBody>)>>::Error,
+        <C as Service<CacheInvalidateRequest>>::Error,
     >;
 
-    type Future = EtagCacheServiceFuture<ReqBody, ResBody, C, P, S>;
+    type Future = EtagCacheServiceFuture<ReqBody, ResBody, C, P, S, R>;
 
     /// `EtagCacheServiceFuture` poll_ready()s the different services depending on whether
     /// the cache should be used
@@ -117,19 +326,49 @@ where
     }
 
     fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
-        match self.passthrough_predicate.should_passthrough_req(&req) {
-            true => EtagCacheServiceFuture::passthrough(
+        if !self.passthrough_predicate.should_passthrough_req(&req) {
+            return EtagCacheServiceFuture::start(
                 self.cache_provider.clone(),
                 self.passthrough_predicate.clone(),
                 self.inner.clone(),
                 req,
-            ),
-            false => EtagCacheServiceFuture::start(
+            )
+            .head_fetches_get(self.head_fetches_get)
+            .single_flight(self.single_flight)
+            .tag_header(self.tag_header.clone())
+            .strip_tag_header(self.strip_tag_header)
+            .refresh_with(self.refresh_spawner.clone());
+        }
+        if self.enforce_preconditions
+            && !is_safe_method(req.method())
+            && has_conditional_headers(req.headers())
+        {
+            return EtagCacheServiceFuture::preconditions(
                 self.cache_provider.clone(),
                 self.passthrough_predicate.clone(),
                 self.inner.clone(),
                 req,
-            ),
+            )
+            .tag_header(self.tag_header.clone())
+            .strip_tag_header(self.strip_tag_header)
+            .refresh_with(self.refresh_spawner.clone());
         }
+        EtagCacheServiceFuture::passthrough(
+            self.cache_provider.clone(),
+            self.passthrough_predicate.clone(),
+            self.inner.clone(),
+            req,
+        )
+        .tag_header(self.tag_header.clone())
+        .strip_tag_header(self.strip_tag_header)
+        .refresh_with(self.refresh_spawner.clone())
     }
 }
+
+/// [RFC 9110 section 9.2.1](https://www.rfc-editor.org/rfc/rfc9110#section-9.2.1)
+fn is_safe_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
@@ -12,6 +12,16 @@ pub trait PassthroughPredicate: Clone {
     /// Returns true if the given inner service response should ignore the
     /// second EtagCache service and not have its ETag calculated and cached
     fn should_passthrough_resp<T>(&mut self, resp: &http::Response<T>) -> bool;
+
+    /// Returns true if the ETag calculated for the given inner service response
+    /// should be a weak validator, e.g. because a layer between the EtagCache and the client
+    /// rewrites the response body.
+    ///
+    /// Only called on responses that are not passed through.
+    /// Defaults to false.
+    fn should_weaken_etag<T>(&mut self, _resp: &http::Response<T>) -> bool {
+        false
+    }
 }
 
 /// A [`PassthroughPredicate`] with sensible defaults for controlling ETag cache behaviour
@@ -0,0 +1,397 @@
+//! Logic shared by the built-in [`CacheProvider`](crate::CacheProvider)s,
+//! which store a [`CacheEntry`] per [`CacheKeyExtractor::Key`]
+
+use http::{
+    header::{LAST_MODIFIED, VARY},
+    Extensions, HeaderMap, HeaderValue, Method,
+};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    hash::Hash,
+    mem::size_of,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    freshness_lifetime, CacheGetResponseResult, CacheKeyExtractor, CacheTags, CachedResponse,
+    EntityTag, PreconditionOutcome, PurgeRequest, RefreshRequest, RequestCredentials, Validators,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct CacheEntry {
+    pub etag: EntityTag,
+    /// [`Epoch::generation`](crate::Epoch::generation) the ETag was generated in
+    pub epoch: u64,
+    pub last_modified: SystemTime,
+    /// `None` if the entry never expires
+    pub expires_at: Option<Instant>,
+    /// `None` if only the ETag is cached
+    pub response: Option<CachedResponse>,
+    /// When the entry was last saved or last triggered a refresh
+    pub refreshed_at: Instant,
+    /// Sorted and deduplicated, see [`entry_tags`]
+    pub tags: Box<[String]>,
+}
+
+impl CacheEntry {
+    pub fn new(
+        etag: EntityTag,
+        epoch: u64,
+        ttl: Option<Duration>,
+        response: Option<CachedResponse>,
+        tags: Box<[String]>,
+    ) -> Self {
+        Self {
+            etag,
+            epoch,
+            last_modified: SystemTime::now(),
+            expires_at: ttl.map(expires_at),
+            response,
+            refreshed_at: Instant::now(),
+            tags,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|t| t <= Instant::now())
+    }
+
+    /// Whether the entry has expired or was saved in an epoch other than `epoch`
+    pub fn is_outdated(&self, epoch: u64) -> bool {
+        self.epoch != epoch || self.is_expired()
+    }
+
+    /// Saves `etag`, `response` and `tags` and restarts the entry's `ttl`,
+    /// only updating its last modified time if the ETag changed
+    pub fn update(
+        &mut self,
+        etag: EntityTag,
+        epoch: u64,
+        ttl: Option<Duration>,
+        response: Option<CachedResponse>,
+        tags: Box<[String]>,
+    ) {
+        if self.etag != etag {
+            self.etag = etag;
+            self.last_modified = SystemTime::now();
+        }
+        self.epoch = epoch;
+        self.expires_at = ttl.map(expires_at);
+        self.response = response;
+        self.refreshed_at = Instant::now();
+        self.tags = tags;
+    }
+
+    /// Returns true at most once per `refresh_after` for an entry that was saved longer than `refresh_after` ago
+    pub fn should_refresh(&mut self, refresh_after: Duration) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.refreshed_at) < refresh_after {
+            return false;
+        }
+        self.refreshed_at = now;
+        true
+    }
+}
+
+/// Saturates instead of panicking on overflow for very large TTLs
+fn expires_at(ttl: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(ttl)
+        .unwrap_or_else(|| now + Duration::from_secs(u32::MAX.into()))
+}
+
+/// The TTL of the cache entry for a response with `headers`, `None` to use the provider's TTL.
+///
+/// The response's [`freshness_lifetime`] is only used if `use_freshness_lifetime` is set.
+/// A lifetime of zero, e.g. from `max-age=0` or an invalid `Expires`, is ignored
+/// so that the ETag is kept to revalidate conditional requests with instead of expiring immediately.
+pub(crate) fn response_ttl(headers: &HeaderMap, use_freshness_lifetime: bool) -> Option<Duration> {
+    match use_freshness_lifetime {
+        true => freshness_lifetime(headers).filter(|lifetime| !lifetime.is_zero()),
+        false => None,
+    }
+}
+
+/// Evaluates `req`'s conditional headers against `entry`, the cache entry for `key` if any.
+///
+/// Expired entries and entries saved in an epoch other than `epoch` are treated as misses.
+/// `GET` and `HEAD` requests whose preconditions pass are served the entry's saved response, if any,
+/// unless they carry credentials the key does not vary on.
+/// [`RefreshRequest`]s always miss.
+pub(crate) fn cache_get_result<T, K: CacheKeyExtractor>(
+    req: &http::Request<T>,
+    key: K::Key,
+    entry: Option<&CacheEntry>,
+    epoch: u64,
+    key_extractor: &K,
+) -> CacheGetResponseResult<K::Key> {
+    let CacheEntry {
+        etag: cache_etag,
+        last_modified,
+        response,
+        ..
+    } = match entry {
+        Some(e) if !e.is_outdated(epoch) && req.extensions().get::<RefreshRequest>().is_none() => e,
+        _ => return CacheGetResponseResult::Miss(key),
+    };
+    let validators = Validators {
+        etag: cache_etag,
+        last_modified: Some(*last_modified),
+    };
+    match validators.evaluate(req) {
+        PreconditionOutcome::Proceed => match response {
+            Some(response)
+                if matches!(*req.method(), Method::GET | Method::HEAD)
+                    && key_covers(
+                        key_extractor,
+                        &HeaderMap::new(),
+                        RequestCredentials::from_headers(req.headers()),
+                    ) =>
+            {
+                let mut response = response.clone();
+                set_cache_headers(&mut response.headers, *last_modified, key_extractor);
+                CacheGetResponseResult::Full(cache_etag.clone(), response)
+            }
+            _ => CacheGetResponseResult::Miss(key),
+        },
+        PreconditionOutcome::NotModified => {
+            let mut header_map = HeaderMap::new();
+            set_cache_headers(&mut header_map, *last_modified, key_extractor);
+            CacheGetResponseResult::Hit(cache_etag.clone(), header_map)
+        }
+        PreconditionOutcome::PreconditionFailed => {
+            CacheGetResponseResult::PreconditionFailed(HeaderMap::new())
+        }
+    }
+}
+
+/// Whether `key_extractor`'s keys vary on every request header named by the `Vary` headers in `headers`
+/// and on every header of `credentials`, so that a response with `headers` to a request with `credentials`
+/// may be served to all requests with the same key. `Vary: *` is never covered.
+pub(crate) fn key_covers<K: CacheKeyExtractor>(
+    key_extractor: &K,
+    headers: &HeaderMap,
+    credentials: RequestCredentials,
+) -> bool {
+    let mut key_headers = HeaderMap::new();
+    key_extractor.set_response_headers(&mut key_headers);
+    let key_vary: Vec<String> = vary_names(&key_headers).collect();
+    vary_names(headers)
+        .chain(credentials.header_names().map(|n| n.as_str().to_owned()))
+        .all(|name| name != "*" && key_vary.contains(&name))
+}
+
+/// Lowercased field names listed by the `Vary` headers in `headers`
+fn vary_names(headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers
+        .get_all(VARY)
+        .iter()
+        .map(|hv| hv.to_str().unwrap_or("*"))
+        .flat_map(|s| s.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+}
+
+/// Evaluates the conditional headers of `req`, a [`PreconditionCheck`](crate::PreconditionCheck),
+/// against `entries`, all cache entries for its target URI.
+///
+/// Outdated entries are ignored, and the result is a miss if there are none left
+/// so that the inner service evaluates the preconditions.
+pub(crate) fn precondition_check_result<'a, T, Key>(
+    req: &http::Request<T>,
+    key: Key,
+    entries: impl IntoIterator<Item = &'a CacheEntry>,
+    epoch: u64,
+) -> CacheGetResponseResult<Key> {
+    let variants: Vec<Validators> = entries
+        .into_iter()
+        .filter(|e| !e.is_outdated(epoch))
+        .map(|e| Validators {
+            etag: &e.etag,
+            last_modified: Some(e.last_modified),
+        })
+        .collect();
+    if variants.is_empty() {
+        return CacheGetResponseResult::Miss(key);
+    }
+    match Validators::evaluate_any(&variants, req) {
+        PreconditionOutcome::PreconditionFailed => {
+            CacheGetResponseResult::PreconditionFailed(HeaderMap::new())
+        }
+        // only GET and HEAD requests are not modified, which are not precondition checks
+        PreconditionOutcome::Proceed | PreconditionOutcome::NotModified => {
+            CacheGetResponseResult::Miss(key)
+        }
+    }
+}
+
+/// Approximate number of bytes a cache entry takes up, counting both inline and heap-allocated data
+pub(crate) fn entry_size<K: CacheKeyExtractor>(key: &K::Key, entry: &CacheEntry) -> usize {
+    let response_size = entry.response.as_ref().map_or(0, |r| {
+        let headers_size: usize = r
+            .headers
+            .iter()
+            .map(|(name, val)| name.as_str().len() + val.len())
+            .sum();
+        headers_size + r.body.len()
+    });
+    // each tag is held by the entry and the tag index, which also holds a clone of the key per tag.
+    // The index's copy of a tag is shared by all entries carrying it, so this is an upper bound.
+    let index_key_size = size_of::<K::Key>() + K::key_heap_size(key);
+    let tags_size: usize = entry
+        .tags
+        .iter()
+        .map(|t| 2 * (size_of::<String>() + t.len()) + index_key_size)
+        .sum();
+    // the URI index likewise holds a clone of the key and a copy of its URI
+    let uri_index_size = size_of::<String>() + K::key_uri(key).len() + index_key_size;
+    size_of::<K::Key>()
+        + K::key_heap_size(key)
+        + size_of::<CacheEntry>()
+        + entry.etag.opaque_tag().len()
+        + response_size
+        + tags_size
+        + uri_index_size
+}
+
+/// The sorted and deduplicated [`CacheTags`] of a response, empty if it has none
+pub(crate) fn entry_tags(resp_extensions: &Extensions) -> Box<[String]> {
+    let mut tags = match resp_extensions.get::<CacheTags>() {
+        Some(CacheTags(tags)) => tags.clone(),
+        None => return Box::default(),
+    };
+    tags.sort_unstable();
+    tags.dedup();
+    tags.into_boxed_slice()
+}
+
+/// Whether `req` selects the cache entry for `key`
+pub(crate) fn purge_matches<K: CacheKeyExtractor>(
+    req: &PurgeRequest<K::Key>,
+    key: &K::Key,
+    entry: &CacheEntry,
+    key_extractor: &K,
+) -> bool
+where
+    K::Key: PartialEq,
+{
+    match req {
+        PurgeRequest::Key(k) => k == key,
+        PurgeRequest::Uri(uri) => key_extractor.matches_uri(key, uri),
+        PurgeRequest::PathPrefix(prefix) => key_extractor.matches_path_prefix(key, prefix),
+        PurgeRequest::Tag(tag) => entry.tags.binary_search(tag).is_ok(),
+        PurgeRequest::Predicate(predicate) => predicate(key),
+        PurgeRequest::All => true,
+    }
+}
+
+/// The set of keys a [`KeyIndex`] maps each name to
+pub(crate) trait KeySet<Key>: Default + IntoIterator<Item = Key> {
+    fn insert(&mut self, key: Key);
+
+    fn remove(&mut self, key: &Key);
+
+    fn is_empty(&self) -> bool;
+}
+
+impl<Key: Ord> KeySet<Key> for BTreeSet<Key> {
+    fn insert(&mut self, key: Key) {
+        BTreeSet::insert(self, key);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        BTreeSet::remove(self, key);
+    }
+
+    fn is_empty(&self) -> bool {
+        BTreeSet::is_empty(self)
+    }
+}
+
+impl<Key: Hash + Eq> KeySet<Key> for HashSet<Key> {
+    fn insert(&mut self, key: Key) {
+        HashSet::insert(self, key);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        HashSet::remove(self, key);
+    }
+
+    fn is_empty(&self) -> bool {
+        HashSet::is_empty(self)
+    }
+}
+
+/// Maps names, such as tags or URIs, to the keys of the cache entries they apply to.
+///
+/// Must be updated whenever an entry is saved or removed.
+#[derive(Debug)]
+pub(crate) struct KeyIndex<S> {
+    keys: HashMap<String, S>,
+}
+
+impl<S> KeyIndex<S> {
+    pub fn new() -> Self {
+        Self {
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn insert<Key: Clone>(&mut self, key: &Key, names: &[impl AsRef<str>])
+    where
+        S: KeySet<Key>,
+    {
+        for name in names.iter().map(AsRef::as_ref) {
+            match self.keys.get_mut(name) {
+                Some(keys) => keys.insert(key.clone()),
+                None => {
+                    let mut keys = S::default();
+                    keys.insert(key.clone());
+                    self.keys.insert(name.to_owned(), keys);
+                }
+            }
+        }
+    }
+
+    pub fn remove<Key>(&mut self, key: &Key, names: &[impl AsRef<str>])
+    where
+        S: KeySet<Key>,
+    {
+        for name in names.iter().map(AsRef::as_ref) {
+            if let Some(keys) = self.keys.get_mut(name) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.keys.remove(name);
+                }
+            }
+        }
+    }
+
+    /// The keys of the entries `name` applies to, if any
+    pub fn get(&self, name: &str) -> Option<&S> {
+        self.keys.get(name)
+    }
+
+    /// Removes and returns the keys of the entries `name` applies to
+    pub fn take<Key>(&mut self, name: &str) -> S
+    where
+        S: KeySet<Key>,
+    {
+        self.keys.remove(name).unwrap_or_default()
+    }
+}
+
+/// Sets the response headers other than `ETag`
+pub(crate) fn set_cache_headers(
+    headers_mut: &mut HeaderMap,
+    last_modified_val: SystemTime,
+    key_extractor: &impl CacheKeyExtractor,
+) {
+    let last_modified_val = httpdate::fmt_http_date(last_modified_val);
+    // unwrap-safety: HTTP-date is always valid ascii
+    headers_mut.append(
+        LAST_MODIFIED,
+        HeaderValue::from_str(&last_modified_val).unwrap(),
+    );
+    key_extractor.set_response_headers(headers_mut);
+}
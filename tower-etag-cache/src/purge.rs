@@ -0,0 +1,32 @@
+use std::fmt::{self, Debug};
+
+/// Selects the cache entries to remove from a built-in [`CacheProvider`](crate::CacheProvider)
+/// outside of the tower stack, e.g. when content is published
+pub enum PurgeRequest<Key> {
+    /// The entry with exactly this key
+    Key(Key),
+    /// All entries whose key matches the URI, see [`CacheKeyExtractor::matches_uri`](crate::CacheKeyExtractor::matches_uri)
+    Uri(http::Uri),
+    /// All entries whose URI path starts with the prefix, see [`CacheKeyExtractor::matches_path_prefix`](crate::CacheKeyExtractor::matches_path_prefix)
+    PathPrefix(String),
+    /// All entries carrying the tag, see [`CacheTags`](crate::CacheTags)
+    Tag(String),
+    /// All entries whose key the predicate returns true for
+    Predicate(Box<dyn Fn(&Key) -> bool + Send>),
+    /// All entries
+    All,
+}
+
+// manual impl since the predicate is not Debug
+impl<Key: Debug> Debug for PurgeRequest<Key> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(k) => f.debug_tuple("Key").field(k).finish(),
+            Self::Uri(uri) => f.debug_tuple("Uri").field(uri).finish(),
+            Self::PathPrefix(prefix) => f.debug_tuple("PathPrefix").field(prefix).finish(),
+            Self::Tag(tag) => f.debug_tuple("Tag").field(tag).finish(),
+            Self::Predicate(_) => f.write_str("Predicate(..)"),
+            Self::All => f.write_str("All"),
+        }
+    }
+}
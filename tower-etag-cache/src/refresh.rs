@@ -0,0 +1,55 @@
+use http::{
+    header::{IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE, RANGE},
+    Method,
+};
+
+/// `http::Request` extension that signals to [`EtagCache`](crate::EtagCache)
+/// that the cache entry for the request is due for a background refresh.
+///
+/// Inserted into [`CacheGetResponse::req`](crate::CacheGetResponse::req) by a [`CacheProvider`](crate::CacheProvider)
+/// along with a [`CacheGetResponseResult::Hit`](crate::CacheGetResponseResult::Hit)
+/// or [`CacheGetResponseResult::Full`](crate::CacheGetResponseResult::Full).
+/// Providers should only do so once per refresh interval so that only one refresh runs at a time.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct StaleEntry;
+
+/// `http::Request` extension that marks a background refresh request sent by [`EtagCache`](crate::EtagCache).
+///
+/// A [`CacheProvider`](crate::CacheProvider)'s cache-lookup `Service` should always return
+/// [`CacheGetResponseResult::Miss`](crate::CacheGetResponseResult::Miss) for such requests
+/// so that the inner service runs and the ETag is recalculated.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct RefreshRequest;
+
+/// Runs the futures that refresh stale cache entries in the background, see [`EtagCache::refresh_with`](crate::EtagCache::refresh_with)
+pub trait RefreshSpawner<Fut> {
+    /// Runs `fut` to completion in the background, ignoring its output
+    fn spawn(&self, fut: Fut);
+}
+
+/// The default [`RefreshSpawner`], which never refreshes cache entries
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct NoRefresh;
+
+impl<Fut> RefreshSpawner<Fut> for NoRefresh {
+    fn spawn(&self, _fut: Fut) {}
+}
+
+/// Turns the request that hit a stale cache entry into an unconditional `GET` [`RefreshRequest`]
+/// for the full representation, so that the ETag of a partial response is never saved
+pub(crate) fn into_refresh_request<T>(mut req: http::Request<T>) -> http::Request<T> {
+    *req.method_mut() = Method::GET;
+    let headers = req.headers_mut();
+    for name in [
+        IF_MATCH,
+        IF_NONE_MATCH,
+        IF_MODIFIED_SINCE,
+        IF_UNMODIFIED_SINCE,
+        IF_RANGE,
+        RANGE,
+    ] {
+        headers.remove(name);
+    }
+    req.extensions_mut().insert(RefreshRequest);
+    req
+}
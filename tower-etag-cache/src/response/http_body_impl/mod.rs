@@ -5,7 +5,7 @@
 
 use std::{
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
 };
 
 use bytes::Bytes;
@@ -36,7 +36,25 @@ impl<ResBody: Body<Data = Bytes>, TResBody: Body<Data = Bytes>> Body
             EtagCacheResBodyProj::Passthrough(b) => b
                 .poll_frame(cx)
                 .map(|p| p.map(|res| res.map_err(EtagCacheResBodyError::Passthrough))),
-            EtagCacheResBodyProj::Hit => Poll::Ready(None),
+            EtagCacheResBodyProj::Head(mut b) => loop {
+                // discard all frames
+                match ready!(b.as_mut().poll_frame(cx)) {
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Poll::Ready(Some(Err(EtagCacheResBodyError::Miss(e)))),
+                    None => return Poll::Ready(None),
+                }
+            },
+            EtagCacheResBodyProj::Full(b) => {
+                let b = std::mem::take(b);
+                if b.is_empty() {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Ok(Frame::data(b))))
+                }
+            }
+            EtagCacheResBodyProj::Hit | EtagCacheResBodyProj::PreconditionFailed => {
+                Poll::Ready(None)
+            }
         }
     }
 }
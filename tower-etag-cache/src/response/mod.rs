@@ -1,6 +1,9 @@
-use http::HeaderMap;
+use bytes::Bytes;
+use http::{header::ETAG, HeaderMap};
 use pin_project::pin_project;
 
+use crate::{CachedResponse, EntityTag};
+
 #[cfg(feature = "http-body-impl")]
 #[cfg_attr(docsrs, doc(cfg(feature = "http-body-impl")))]
 pub mod http_body_impl;
@@ -13,15 +16,56 @@ pub enum EtagCacheResBody<ResBody, TResBody> {
 
     /// 304 response. Should return empty http body
     Hit,
+
+    /// 412 response. Should return empty http body
+    PreconditionFailed,
+
+    /// Response to a `HEAD` request whose `GET` representation was fetched,
+    /// see [`EtagCache::head_fetches_get`](crate::EtagCache::head_fetches_get).
+    /// Should return empty http body.
+    ///
+    /// Servers may never poll it, so the cache provider must not rely on the transformed body
+    /// being polled to save its ETag, see [`DiscardedBody`](crate::DiscardedBody)
+    Head(#[pin] TResBody),
+
+    /// 200 response served from the cache. Should return this http body
+    Full(Bytes),
 }
 
 impl<ResBody, TResBody> EtagCacheResBody<ResBody, TResBody> {
-    pub fn hit_resp(headers: HeaderMap) -> http::Result<http::Response<Self>> {
+    pub fn hit_resp(etag: &EntityTag, headers: HeaderMap) -> http::Result<http::Response<Self>> {
         let mut builder = http::response::Builder::new().status(http::StatusCode::NOT_MODIFIED);
-        *builder.headers_mut().unwrap() = headers;
+        let headers_mut = builder.headers_mut().unwrap();
+        *headers_mut = headers;
+        headers_mut.insert(ETAG, etag.to_header_value());
         builder.body(Self::Hit)
     }
 
+    pub fn precondition_failed_resp(headers: HeaderMap) -> http::Result<http::Response<Self>> {
+        let mut builder =
+            http::response::Builder::new().status(http::StatusCode::PRECONDITION_FAILED);
+        *builder.headers_mut().unwrap() = headers;
+        builder.body(Self::PreconditionFailed)
+    }
+
+    /// Pass `head = true` to return an empty http body for a `HEAD` request,
+    /// keeping the `Content-Length` header of the cached body
+    pub fn full_resp(
+        etag: &EntityTag,
+        CachedResponse { headers, body }: CachedResponse,
+        head: bool,
+    ) -> http::Result<http::Response<Self>> {
+        let mut builder = http::response::Builder::new().status(http::StatusCode::OK);
+        let headers_mut = builder.headers_mut().unwrap();
+        *headers_mut = headers;
+        headers_mut.insert(ETAG, etag.to_header_value());
+        let body = match head {
+            true => Bytes::new(),
+            false => body,
+        };
+        builder.body(Self::Full(body))
+    }
+
     pub fn passthrough_resp(resp: http::Response<ResBody>) -> http::Response<Self> {
         let (parts, body) = resp.into_parts();
         http::Response::from_parts(parts, Self::Passthrough(body))
@@ -31,4 +75,9 @@ impl<ResBody, TResBody> EtagCacheResBody<ResBody, TResBody> {
         let (parts, body) = resp.into_parts();
         http::Response::from_parts(parts, Self::Miss(body))
     }
+
+    pub fn head_resp(resp: http::Response<TResBody>) -> http::Response<Self> {
+        let (parts, body) = resp.into_parts();
+        http::Response::from_parts(parts, Self::Head(body))
+    }
 }
@@ -0,0 +1,30 @@
+//! [`EtagGenerator`] that calculates an [`EntityTag`] as the base64-encoded SHA-256 hash of the response body
+
+use data_encoding::BASE64;
+use sha2::{Digest, Sha256};
+
+use crate::{EntityTag, EtagGenerator, EtagHasher};
+
+/// Calculates strong ETags as the base64-encoded SHA-256 hash of the body bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Sha256EtagGenerator;
+
+impl EtagGenerator for Sha256EtagGenerator {
+    type Hasher = Sha256;
+
+    fn hasher(&self, _parts: &http::response::Parts) -> Self::Hasher {
+        Sha256::new()
+    }
+}
+
+impl EtagHasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finish(&mut self) -> EntityTag {
+        let val = BASE64.encode(&self.finalize_reset());
+        // unwrap-safety: base64 alphabet and padding chars are all valid etagc
+        EntityTag::strong(val).unwrap()
+    }
+}
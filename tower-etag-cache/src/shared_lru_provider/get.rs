@@ -0,0 +1,62 @@
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+    hash::Hash,
+    task::{Context, Poll},
+};
+
+use tower_service::Service;
+
+use crate::{
+    provider_util::{cache_get_result, precondition_check_result},
+    CacheGetResponse, CacheGetResponseResult, CacheKeyExtractor, PreconditionCheck, StaleEntry,
+};
+
+use super::SharedLruProvider;
+
+impl<ReqBody, G, K: CacheKeyExtractor> Service<http::Request<ReqBody>> for SharedLruProvider<G, K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    type Response = CacheGetResponse<ReqBody, K::Key>;
+
+    type Error = Infallible;
+
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut req: http::Request<ReqBody>) -> Self::Future {
+        let key = self.key_extractor.extract(&req);
+        if req.extensions().get::<PreconditionCheck>().is_some() {
+            let uri = self.key_extractor.uri_string(req.uri());
+            let shard = self.uri_shard(&uri);
+            let entries = shard
+                .uri_entries(&uri)
+                .filter(|(k, _)| self.key_extractor.matches_uri(k, req.uri()))
+                .map(|(_, e)| e);
+            let result = precondition_check_result(&req, key, entries, self.epoch.generation());
+            return ready(Ok(CacheGetResponse { req, result }));
+        }
+        let mut shard = self.shard(&key);
+        let result = cache_get_result(
+            &req,
+            key.clone(),
+            shard.get(&key),
+            self.epoch.generation(),
+            &self.key_extractor,
+        );
+        if let (Some(refresh_after), Some(entry)) = (self.refresh_after, shard.get_mut(&key)) {
+            let is_hit = matches!(
+                result,
+                CacheGetResponseResult::Hit(..) | CacheGetResponseResult::Full(..)
+            );
+            if is_hit && entry.should_refresh(refresh_after) {
+                req.extensions_mut().insert(StaleEntry);
+            }
+        }
+        ready(Ok(CacheGetResponse { req, result }))
+    }
+}
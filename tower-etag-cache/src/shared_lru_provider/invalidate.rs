@@ -0,0 +1,32 @@
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+    hash::Hash,
+    task::{Context, Poll},
+};
+
+use tower_service::Service;
+
+use crate::{CacheInvalidateRequest, CacheKeyExtractor};
+
+use super::SharedLruProvider;
+
+impl<G, K: CacheKeyExtractor> Service<CacheInvalidateRequest> for SharedLruProvider<G, K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    type Response = ();
+
+    type Error = Infallible;
+
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: CacheInvalidateRequest) -> Self::Future {
+        self.invalidate(req);
+        ready(Ok(()))
+    }
+}
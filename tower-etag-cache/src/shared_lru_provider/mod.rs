@@ -0,0 +1,444 @@
+//! An in-memory [`CacheProvider`] backed by sharded, mutex-guarded `LruCache`s
+//! that are accessed directly by the calling task
+
+use bytes::Bytes;
+use http_body::Body;
+use http_body_util::Full;
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use crate::{
+    base64_blake3_body_etag::Base64Blake3EtagGenerator,
+    provider_util::purge_matches,
+    simple_etag_cache_key::{SimpleEtagCacheKey, SimpleEtagCacheKeyExtractor},
+    CacheInvalidateRequest, CacheKeyExtractor, CacheProvider, Epoch, EtagGenerator, PurgeRequest,
+};
+
+mod get;
+mod invalidate;
+mod put;
+mod shard;
+
+use shard::Shard;
+
+pub use put::*;
+
+/// The default cache key type
+pub type SharedLruProviderCacheKey = SimpleEtagCacheKey;
+
+/// A basic in-memory LruCache-backed cache provider that can be used in place of
+/// [`ConstLruProvider`](crate::const_lru_provider::ConstLruProvider).
+///
+/// Entries are split across shards by the hash of their key's URI, each behind its own `Mutex`,
+/// so Get, Put and Invalidate resolve inline without a channel round trip.
+/// Response bodies are buffered and hashed in the caller's task before the shard is locked.
+///
+/// Uses keys calculated by a [`CacheKeyExtractor`], [`SimpleEtagCacheKeyExtractor`]
+/// unless changed with [`SharedLruProvider::key_extractor`],
+/// and ETags calculated by an [`EtagGenerator`], [`Base64Blake3EtagGenerator`]
+/// unless changed with [`SharedLruProvider::etag_generator`].
+///
+/// Also stores the `SystemTime` of when the cache entry was created, which serves as the response's
+/// last-modified header value
+///
+/// Evicts entries by entry count, and by approximate total bytes
+/// if [`SharedLruProvider::set_max_bytes`] is called.
+///
+/// Entries expire after the TTL set by [`SharedLruProvider::ttl`], or their response's
+/// [`freshness_lifetime`](crate::freshness_lifetime) if [`SharedLruProvider::use_freshness_lifetime`] is set,
+/// and are then treated as misses.
+///
+/// Prefixes ETags with its [`Epoch`], if set, and treats entries saved in earlier epochs as misses.
+///
+/// Indexes entries by the [`CacheTags`](crate::CacheTags) of their responses
+/// for [`PurgeRequest::Tag`].
+///
+/// Marks entries for background refresh if [`SharedLruProvider::refresh_after`] is set.
+///
+/// Evaluates the preconditions of [`PreconditionCheck`](crate::PreconditionCheck)s
+/// against all entries for the request's URI.
+///
+/// Does not coalesce concurrent misses, [`SingleFlightRequest`](crate::SingleFlightRequest)s are ignored.
+///
+/// Cheap to clone, all clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct SharedLruProvider<
+    G = Base64Blake3EtagGenerator,
+    K: CacheKeyExtractor = SimpleEtagCacheKeyExtractor,
+> {
+    shards: Arc<[Mutex<Shard<K>>]>,
+    hash_builder: RandomState,
+    ttl: Option<Duration>,
+    use_freshness_lifetime: bool,
+    refresh_after: Option<Duration>,
+    epoch: Epoch,
+    generator: G,
+    key_extractor: K,
+}
+
+impl SharedLruProvider {
+    /// Creates a SharedLruProvider that holds up to `capacity` entries,
+    /// split across a number of shards based on the available parallelism
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        let shards = std::thread::available_parallelism()
+            .map_or(1, |n| n.get() * 4)
+            .next_power_of_two();
+        // unwrap-safety: next_power_of_two() is at least 1
+        Self::with_shards(capacity, NonZeroUsize::new(shards).unwrap())
+    }
+
+    /// `capacity` is split as evenly as possible across `shards`.
+    ///
+    /// `shards` is capped at `capacity`.
+    pub fn with_shards(capacity: NonZeroUsize, shards: NonZeroUsize) -> Self {
+        let shards = shards.min(capacity).get();
+        Self {
+            shards: (0..shards)
+                .map(|i| Mutex::new(Shard::new(shard_capacity(capacity, shards, i))))
+                .collect(),
+            hash_builder: RandomState::new(),
+            ttl: None,
+            use_freshness_lifetime: false,
+            refresh_after: None,
+            epoch: Epoch::default(),
+            generator: Base64Blake3EtagGenerator,
+            key_extractor: SimpleEtagCacheKeyExtractor,
+        }
+    }
+}
+
+impl<G, K: CacheKeyExtractor> SharedLruProvider<G, K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    /// Calculates ETags with `generator` instead of the current one
+    pub fn etag_generator<G2: EtagGenerator>(self, generator: G2) -> SharedLruProvider<G2, K> {
+        SharedLruProvider {
+            shards: self.shards,
+            hash_builder: self.hash_builder,
+            ttl: self.ttl,
+            use_freshness_lifetime: self.use_freshness_lifetime,
+            refresh_after: self.refresh_after,
+            epoch: self.epoch,
+            generator,
+            key_extractor: self.key_extractor,
+        }
+    }
+
+    /// Calculates cache keys with `key_extractor` instead of the current one.
+    ///
+    /// Keeps the shards' capacities and byte budgets, but discards all cached entries.
+    pub fn key_extractor<K2: CacheKeyExtractor>(self, key_extractor: K2) -> SharedLruProvider<G, K2>
+    where
+        K2::Key: Hash + Eq + Clone,
+    {
+        let shards = self
+            .shards
+            .iter()
+            .map(|s| {
+                let s = lock(s);
+                let mut shard = Shard::new(s.cap());
+                shard.set_max_bytes(s.max_bytes());
+                Mutex::new(shard)
+            })
+            .collect();
+        SharedLruProvider {
+            shards,
+            hash_builder: self.hash_builder,
+            ttl: self.ttl,
+            use_freshness_lifetime: self.use_freshness_lifetime,
+            refresh_after: self.refresh_after,
+            epoch: self.epoch,
+            generator: self.generator,
+            key_extractor,
+        }
+    }
+
+    /// The provider's deployment epoch, shared by all clones of this provider.
+    ///
+    /// `provider.epoch().set(Some("build-42"))` invalidates every previously generated ETag.
+    pub fn epoch(&self) -> &Epoch {
+        &self.epoch
+    }
+
+    /// Replaces the provider's deployment epoch with `epoch`,
+    /// e.g. one created with [`Epoch::new`] to prefix ETags from the first response on.
+    ///
+    /// Should be called before the provider is cloned or used, since clones keep the old epoch.
+    pub fn with_epoch(mut self, epoch: Epoch) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Entries expire this long after their ETag was last saved,
+    /// unless their response has a [`freshness_lifetime`](crate::freshness_lifetime)
+    /// and [`Self::use_freshness_lifetime`] is set, which takes precedence.
+    ///
+    /// `None`, the default, never expires entries unless [`Self::use_freshness_lifetime`] is set.
+    pub fn ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// If true, entries expire after their response's [`freshness_lifetime`](crate::freshness_lifetime)
+    /// from `Cache-Control: s-maxage`/`max-age` or `Expires`.
+    ///
+    /// A lifetime of zero, e.g. `max-age=0`, `Expires` equal to `Date` or an invalid `Expires`,
+    /// requires the response to be revalidated on every use, so the ETag is kept
+    /// with the TTL set by [`Self::ttl`] instead of expiring immediately.
+    ///
+    /// false, the default, ignores the response's freshness lifetime.
+    pub fn use_freshness_lifetime(mut self, use_freshness_lifetime: bool) -> Self {
+        self.use_freshness_lifetime = use_freshness_lifetime;
+        self
+    }
+
+    /// Entries saved longer than this ago are still used to respond,
+    /// but mark the request as [`StaleEntry`](crate::StaleEntry) at most once per interval
+    /// so that [`EtagCache::refresh_with`](crate::EtagCache::refresh_with) recalculates the ETag in the background.
+    ///
+    /// `None`, the default, never marks entries as stale.
+    pub fn refresh_after(mut self, refresh_after: Option<Duration>) -> Self {
+        self.refresh_after = refresh_after;
+        self
+    }
+
+    /// Changes the capacity to `capacity` entries, split as evenly as possible across the existing shards.
+    ///
+    /// Shrinking evicts the least recently used entries of each shard.
+    /// Growing keeps all existing entries.
+    ///
+    /// If `capacity` is less than the number of shards, the remaining shards hold no entries,
+    /// so keys that hash to them are not cached.
+    pub fn resize(&self, capacity: NonZeroUsize) {
+        for (i, shard) in self.shards.iter().enumerate() {
+            lock(shard).resize(shard_capacity(capacity, self.shards.len(), i));
+        }
+    }
+
+    /// Evicts least recently used entries once the approximate total bytes of
+    /// all cached keys and ETags exceeds `max_bytes`, split evenly across shards,
+    /// in addition to the entry-count capacity.
+    ///
+    /// `None`, the default, only evicts by entry count.
+    pub fn set_max_bytes(&self, max_bytes: Option<usize>) {
+        let shard_max_bytes = max_bytes.map(|m| m.div_ceil(self.shards.len()));
+        for shard in self.shards.iter() {
+            lock(shard).set_max_bytes(shard_max_bytes);
+        }
+    }
+
+    /// Total capacity of all shards
+    pub fn cap(&self) -> usize {
+        self.shards.iter().map(|s| lock(s).cap()).sum()
+    }
+
+    /// Total number of entries in all shards
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| lock(s).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate total bytes of the keys and ETags currently in the cache
+    pub fn bytes(&self) -> usize {
+        self.shards.iter().map(|s| lock(s).bytes()).sum()
+    }
+
+    /// Removes the selected entries from outside the tower stack, e.g. when content is published.
+    ///
+    /// Returns the number of entries removed.
+    pub fn purge(&self, req: PurgeRequest<K::Key>) -> usize {
+        match req {
+            PurgeRequest::Key(key) => self.shard(&key).remove(&key).into(),
+            PurgeRequest::Uri(uri) => self.remove_uri(&uri),
+            PurgeRequest::Tag(tag) => self
+                .shards
+                .iter()
+                .map(|shard| lock(shard).remove_tagged(&tag))
+                .sum(),
+            req => self
+                .shards
+                .iter()
+                .map(|shard| {
+                    lock(shard).invalidate(|k, e| purge_matches(&req, k, e, &self.key_extractor))
+                })
+                .sum(),
+        }
+    }
+
+    fn shard(&self, key: &K::Key) -> MutexGuard<'_, Shard<K>> {
+        self.uri_shard(K::key_uri(key))
+    }
+
+    /// The shard holding all entries for `uri`, see [`CacheKeyExtractor::key_uri`]
+    fn uri_shard(&self, uri: &str) -> MutexGuard<'_, Shard<K>> {
+        let i = self.hash_builder.hash_one(uri) as usize % self.shards.len();
+        lock(&self.shards[i])
+    }
+
+    /// Removes all entries whose uri matches any of the request's URIs
+    fn invalidate(&self, CacheInvalidateRequest { uris }: CacheInvalidateRequest) {
+        for uri in uris.iter() {
+            self.remove_uri(uri);
+        }
+    }
+
+    /// Removes all entries whose uri matches `uri`, returning the number of entries removed
+    fn remove_uri(&self, uri: &http::Uri) -> usize {
+        let uri_string = self.key_extractor.uri_string(uri);
+        self.uri_shard(&uri_string)
+            .remove_uri(&uri_string, |k| self.key_extractor.matches_uri(k, uri))
+    }
+}
+
+/// The capacity of the `i`th of `shards` shards, which add up to `capacity`
+fn shard_capacity(capacity: NonZeroUsize, shards: usize, i: usize) -> usize {
+    capacity.get() / shards + usize::from(i < capacity.get() % shards)
+}
+
+/// A panic while a shard is locked cannot leave an entry partially written, so poisoning is ignored
+fn lock<K: CacheKeyExtractor>(shard: &Mutex<Shard<K>>) -> MutexGuard<'_, Shard<K>> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<ReqBody, ResBody: Body, G: EtagGenerator + Clone, K: CacheKeyExtractor + Clone>
+    CacheProvider<ReqBody, ResBody> for SharedLruProvider<G, K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    type Key = K::Key;
+    type TResBody = Full<Bytes>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{simple_etag_cache_key::SimpleEtagCacheKeyExtractor, CacheKeyExtractor, EntityTag};
+    use http::HeaderValue;
+    use std::mem::size_of;
+
+    fn nz(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    fn insert(provider: &SharedLruProvider, n: usize) {
+        for i in 0..n {
+            let key = SimpleEtagCacheKey {
+                uri_string: format!("/{i}"),
+                ..Default::default()
+            };
+            let etag = EntityTag::strong(i.to_string()).unwrap();
+            provider
+                .shard(&key)
+                .insert_etag(key, etag, 0, None, Box::default());
+        }
+    }
+
+    #[test]
+    fn cap_is_split_exactly() {
+        let provider = SharedLruProvider::with_shards(nz(1000), nz(16));
+        assert_eq!(provider.cap(), 1000);
+        assert_eq!(provider.shards.len(), 16);
+
+        let provider = SharedLruProvider::with_shards(nz(3), nz(16));
+        assert_eq!(provider.cap(), 3);
+        assert_eq!(provider.shards.len(), 3);
+    }
+
+    #[test]
+    fn resize_below_shard_count() {
+        let provider = SharedLruProvider::with_shards(nz(1000), nz(16));
+        insert(&provider, 100);
+        assert_eq!(provider.len(), 100);
+
+        provider.resize(nz(2));
+        assert_eq!(provider.cap(), 2);
+        assert!(provider.len() <= 2);
+        insert(&provider, 100);
+        assert!(provider.len() <= 2);
+
+        provider.resize(nz(1000));
+        assert_eq!(provider.cap(), 1000);
+        insert(&provider, 100);
+        assert_eq!(provider.len(), 100);
+    }
+
+    #[test]
+    fn resize_uneven() {
+        let provider = SharedLruProvider::with_shards(nz(16), nz(16));
+        provider.resize(nz(37));
+        assert_eq!(provider.cap(), 37);
+        insert(&provider, 1000);
+        assert!(provider.len() <= 37);
+    }
+
+    /// Inserts the keys for `/a` with two `Accept` values, then `/b`
+    fn insert_variants(provider: &SharedLruProvider) {
+        let variant = |uri: &str, accept| SimpleEtagCacheKey {
+            uri_string: uri.to_owned(),
+            accept: vec![HeaderValue::from_static(accept)],
+            ..Default::default()
+        };
+        let etag = EntityTag::strong("e").unwrap();
+        for key in [
+            variant("/a", "text/html"),
+            variant("/a", "application/json"),
+            variant("/b", "text/html"),
+        ] {
+            provider
+                .shard(&key)
+                .insert_etag(key, etag.clone(), 0, None, Box::default());
+        }
+    }
+
+    #[test]
+    fn uri_index_follows_evictions() {
+        let provider = SharedLruProvider::with_shards(nz(2), nz(1));
+        insert_variants(&provider);
+        // the least recently used variant of /a was evicted
+        let accepts: Vec<_> = provider
+            .uri_shard("/a")
+            .uri_entries("/a")
+            .map(|(k, _)| k.accept.clone())
+            .collect();
+        assert_eq!(accepts, [[HeaderValue::from_static("application/json")]]);
+        assert_eq!(provider.uri_shard("/b").uri_entries("/b").count(), 1);
+    }
+
+    #[test]
+    fn purge_uri_removes_all_its_variants() {
+        let provider = SharedLruProvider::with_shards(nz(100), nz(4));
+        insert_variants(&provider);
+        let uri = http::Uri::from_static("/a");
+        assert_eq!(provider.purge(PurgeRequest::Uri(uri.clone())), 2);
+        assert_eq!(provider.len(), 1);
+        assert_eq!(provider.purge(PurgeRequest::Uri(uri)), 0);
+    }
+
+    #[test]
+    fn bytes_count_tag_index() {
+        let provider = SharedLruProvider::with_shards(nz(16), nz(1));
+        let key = SimpleEtagCacheKey {
+            uri_string: "/a-long-uri-that-the-tag-index-clones".to_owned(),
+            ..Default::default()
+        };
+        let etag = EntityTag::strong("e").unwrap();
+        provider
+            .shard(&key)
+            .insert_etag(key.clone(), etag.clone(), 0, None, Box::default());
+        let untagged = provider.bytes();
+        provider
+            .shard(&key)
+            .insert_etag(key.clone(), etag, 0, None, Box::new(["t".to_owned()]));
+        let key_size =
+            size_of::<SimpleEtagCacheKey>() + SimpleEtagCacheKeyExtractor::key_heap_size(&key);
+        assert!(provider.bytes() - untagged >= key_size + 2 * (size_of::<String>() + 1));
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http::header::ETAG;
+use http_body::Body;
+use http_body_util::{combinators::Collect, BodyExt, Full};
+use pin_project::pin_project;
+use tower_service::Service;
+
+use crate::{
+    provider_util::{entry_tags, response_ttl, set_cache_headers},
+    CacheKeyExtractor, DiscardedBody, EpochValue, EtagGenerator, HeadResponse, RequestCredentials,
+    WeakEtag,
+};
+
+use super::SharedLruProvider;
+
+/// Buffers and hashes the response body, then saves its ETag to the [`SharedLruProvider`]
+#[pin_project]
+pub struct SharedLruProviderPutFuture<ResBody: Body, G, K: CacheKeyExtractor> {
+    #[pin]
+    collect: Collect<ResBody>,
+    provider: SharedLruProvider<G, K>,
+    key: Option<K::Key>,
+    parts: Option<http::response::Parts>,
+    weak: bool,
+    /// The provider's epoch when the response was received
+    epoch: EpochValue,
+    /// true if the response has the [`HeadResponse`] extension and no ETag should be saved
+    head: bool,
+    /// The response's freshness lifetime, see [`SharedLruProvider::use_freshness_lifetime`]
+    ttl: Option<Duration>,
+    /// The response's [`CacheTags`](crate::CacheTags), taken when the ETag is saved
+    tags: Box<[String]>,
+}
+
+impl<ResBody: Body, G: EtagGenerator, K: CacheKeyExtractor> Future
+    for SharedLruProviderPutFuture<ResBody, G, K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    type Output = Result<http::Response<Full<Bytes>>, ResBody::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let body = ready!(this.collect.poll(cx))?.to_bytes();
+        if *this.head {
+            // unwrap-safety: key and parts only taken once, on completion
+            let key = this.key.take().unwrap();
+            let mut parts = this.parts.take().unwrap();
+            let saved = this
+                .provider
+                .shard(&key)
+                .get(&key)
+                .filter(|e| !e.is_outdated(this.provider.epoch.generation()))
+                .map(|e| (e.etag.clone(), e.last_modified));
+            match saved {
+                Some((etag, last_modified)) => {
+                    parts.headers.append(ETAG, etag.to_header_value());
+                    set_cache_headers(
+                        &mut parts.headers,
+                        last_modified,
+                        &this.provider.key_extractor,
+                    );
+                }
+                None => this
+                    .provider
+                    .key_extractor
+                    .set_response_headers(&mut parts.headers),
+            }
+            return Poll::Ready(Ok(http::Response::from_parts(parts, Full::new(body))));
+        }
+        // unwrap-safety: parts only taken on completion
+        let etag = this
+            .provider
+            .generator
+            .generate(this.parts.as_ref().unwrap(), &body);
+        let etag = match this.weak {
+            true => etag.into_weak(),
+            false => etag,
+        };
+        let etag = this.epoch.apply(etag);
+        // unwrap-safety: key and parts only taken once, on completion
+        let key = this.key.take().unwrap();
+        let ttl = this.ttl.or(this.provider.ttl);
+        let last_modified = this.provider.shard(&key).insert_etag(
+            key,
+            etag.clone(),
+            this.epoch.generation(),
+            ttl,
+            std::mem::take(this.tags),
+        );
+        let mut parts = this.parts.take().unwrap();
+        parts.headers.append(ETAG, etag.to_header_value());
+        set_cache_headers(
+            &mut parts.headers,
+            last_modified,
+            &this.provider.key_extractor,
+        );
+        Poll::Ready(Ok(http::Response::from_parts(parts, Full::new(body))))
+    }
+}
+
+impl<ResBody: Body, G: EtagGenerator + Clone, K: CacheKeyExtractor + Clone>
+    Service<(K::Key, http::Response<ResBody>)> for SharedLruProvider<G, K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    type Response = http::Response<Full<Bytes>>;
+
+    type Error = ResBody::Error;
+
+    type Future = SharedLruProviderPutFuture<ResBody, G, K>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, (key, resp): (K::Key, http::Response<ResBody>)) -> Self::Future {
+        let (mut parts, body) = resp.into_parts();
+        let weak = parts.extensions.remove::<WeakEtag>().is_some();
+        let head = parts.extensions.remove::<HeadResponse>().is_some();
+        // bodies are always read to completion here
+        parts.extensions.remove::<DiscardedBody>();
+        // only ETags are saved, which may be shared
+        parts.extensions.remove::<RequestCredentials>();
+        let ttl = response_ttl(&parts.headers, self.use_freshness_lifetime);
+        let tags = entry_tags(&parts.extensions);
+        SharedLruProviderPutFuture {
+            collect: body.collect(),
+            provider: self.clone(),
+            key: Some(key),
+            parts: Some(parts),
+            weak,
+            epoch: self.epoch.get(),
+            head,
+            ttl,
+            tags,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use lru::LruCache;
+use std::{
+    collections::HashSet,
+    fmt::{self, Debug},
+    hash::Hash,
+    num::NonZeroUsize,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    provider_util::{entry_size, CacheEntry, KeyIndex},
+    CacheKeyExtractor, EntityTag,
+};
+
+/// One of [`SharedLruProvider`](super::SharedLruProvider)'s independently locked LRU caches
+pub(super) struct Shard<K: CacheKeyExtractor> {
+    lru: LruCache<K::Key, CacheEntry>,
+    /// Can be 0 unlike the `LruCache`'s capacity, in which case no entries are kept
+    cap: usize,
+    /// Approximate total bytes of all entries
+    bytes: usize,
+    max_bytes: Option<usize>,
+    /// Keys of the entries carrying each tag
+    tag_index: KeyIndex<HashSet<K::Key>>,
+    /// Keys of the entries for each URI, see [`CacheKeyExtractor::key_uri`]
+    uri_index: KeyIndex<HashSet<K::Key>>,
+}
+
+// manual impl since keys are not required to be Debug
+impl<K: CacheKeyExtractor> Debug for Shard<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shard")
+            .field("cap", &self.cap)
+            .field("bytes", &self.bytes)
+            .field("max_bytes", &self.max_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: CacheKeyExtractor> Shard<K>
+where
+    K::Key: Hash + Eq + Clone,
+{
+    pub fn new(cap: usize) -> Self {
+        Self {
+            lru: LruCache::new(NonZeroUsize::new(cap).unwrap_or(NonZeroUsize::MIN)),
+            cap,
+            bytes: 0,
+            max_bytes: None,
+            tag_index: KeyIndex::new(),
+            uri_index: KeyIndex::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &K::Key) -> Option<&CacheEntry> {
+        self.lru.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K::Key) -> Option<&mut CacheEntry> {
+        self.lru.get_mut(key)
+    }
+
+    /// The entries whose keys have `uri` as their [`CacheKeyExtractor::key_uri`].
+    /// Does not update the entries' recency
+    pub fn uri_entries(&self, uri: &str) -> impl Iterator<Item = (&K::Key, &CacheEntry)> {
+        self.uri_index
+            .get(uri)
+            .into_iter()
+            .flatten()
+            .filter_map(|k| Some((k, self.lru.peek(k)?)))
+    }
+
+    /// Returns the last modified time of the cache entry for `key` after saving `etag` and `tags` to it
+    pub fn insert_etag(
+        &mut self,
+        key: K::Key,
+        etag: EntityTag,
+        epoch: u64,
+        ttl: Option<Duration>,
+        tags: Box<[String]>,
+    ) -> SystemTime {
+        let (last_modified, new_size) = match self.lru.get_mut(&key) {
+            Some(curr_val) => {
+                self.bytes -= entry_size::<K>(&key, curr_val);
+                self.tag_index.remove(&key, &curr_val.tags);
+                self.tag_index.insert(&key, &tags);
+                curr_val.update(etag, epoch, ttl, None, tags);
+                (curr_val.last_modified, entry_size::<K>(&key, curr_val))
+            }
+            None => {
+                self.tag_index.insert(&key, &tags);
+                self.uri_index.insert(&key, &[K::key_uri(&key)]);
+                let entry = CacheEntry::new(etag, epoch, ttl, None, tags);
+                let last_modified = entry.last_modified;
+                let new_size = entry_size::<K>(&key, &entry);
+                if let Some((k, e)) = self.lru.push(key, entry) {
+                    self.bytes -= entry_size::<K>(&k, &e);
+                    self.tag_index.remove(&k, &e.tags);
+                    self.uri_index.remove(&k, &[K::key_uri(&k)]);
+                }
+                (last_modified, new_size)
+            }
+        };
+        self.bytes += new_size;
+        self.evict_over_cap();
+        self.evict_over_budget();
+        last_modified
+    }
+
+    /// Removes all entries that match, returning the number of entries removed
+    pub fn invalidate(&mut self, matches: impl Fn(&K::Key, &CacheEntry) -> bool) -> usize {
+        let keys: Vec<K::Key> = self
+            .lru
+            .iter()
+            .filter(|(k, e)| matches(k, e))
+            .map(|(k, _)| k.clone())
+            .collect();
+        for k in keys.iter() {
+            self.remove(k);
+        }
+        keys.len()
+    }
+
+    /// Removes the entries for `uri` whose keys match, returning the number of entries removed
+    pub fn remove_uri(&mut self, uri: &str, matches: impl Fn(&K::Key) -> bool) -> usize {
+        let keys: Vec<K::Key> = self
+            .uri_index
+            .get(uri)
+            .into_iter()
+            .flatten()
+            .filter(|k| matches(k))
+            .cloned()
+            .collect();
+        for k in keys.iter() {
+            self.remove(k);
+        }
+        keys.len()
+    }
+
+    /// Returns true if there was an entry for `key`
+    pub fn remove(&mut self, key: &K::Key) -> bool {
+        match self.lru.pop(key) {
+            Some(entry) => {
+                self.bytes -= entry_size::<K>(key, &entry);
+                self.tag_index.remove(key, &entry.tags);
+                self.uri_index.remove(key, &[K::key_uri(key)]);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evicts least recently used entries if shrinking
+    pub fn resize(&mut self, cap: usize) {
+        self.cap = cap;
+        self.evict_over_cap();
+        self.lru
+            .resize(NonZeroUsize::new(cap).unwrap_or(NonZeroUsize::MIN));
+    }
+
+    /// Removes the entries carrying `tag`, returning the number of entries removed
+    pub fn remove_tagged(&mut self, tag: &str) -> usize {
+        let keys = self.tag_index.take(tag);
+        keys.iter().filter(|k| self.remove(k)).count()
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.evict_over_budget();
+    }
+
+    pub fn max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    pub fn cap(&self) -> usize {
+        self.cap
+    }
+
+    pub fn len(&self) -> usize {
+        self.lru.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Only evicts if `cap` is 0, the `LruCache` evicts on insert otherwise
+    fn evict_over_cap(&mut self) {
+        while self.lru.len() > self.cap && self.pop_lru() {}
+    }
+
+    /// Evicts least recently used entries until the total bytes is within `max_bytes`
+    fn evict_over_budget(&mut self) {
+        let max_bytes = match self.max_bytes {
+            Some(m) => m,
+            None => return,
+        };
+        while self.bytes > max_bytes && self.pop_lru() {}
+    }
+
+    /// Returns false if empty
+    fn pop_lru(&mut self) -> bool {
+        match self.lru.pop_lru() {
+            Some((k, entry)) => {
+                self.bytes -= entry_size::<K>(&k, &entry);
+                self.tag_index.remove(&k, &entry.tags);
+                self.uri_index.remove(&k, &[K::key_uri(&k)]);
+                true
+            }
+            None => false,
+        }
+    }
+}
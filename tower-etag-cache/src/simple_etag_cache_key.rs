@@ -4,6 +4,9 @@ use http::{
     header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, VARY},
     HeaderMap, HeaderName, HeaderValue,
 };
+use std::mem::size_of;
+
+use crate::{uri_path_starts_with, CacheKeyExtractor};
 
 /// Cache key derived from uri, and varying by the following request headers:
 /// - Accept
@@ -40,6 +43,49 @@ pub fn calc_simple_etag_cache_key<T>(req: &http::Request<T>) -> SimpleEtagCacheK
     }
 }
 
+/// [`CacheKeyExtractor`] for [`SimpleEtagCacheKey`].
+///
+/// The default key extractor of the built-in providers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SimpleEtagCacheKeyExtractor;
+
+impl CacheKeyExtractor for SimpleEtagCacheKeyExtractor {
+    type Key = SimpleEtagCacheKey;
+
+    fn extract<T>(&self, req: &http::Request<T>) -> Self::Key {
+        calc_simple_etag_cache_key(req)
+    }
+
+    fn set_response_headers(&self, headers_mut: &mut HeaderMap) {
+        SimpleEtagCacheKey::set_response_headers(headers_mut);
+    }
+
+    fn matches_uri(&self, key: &Self::Key, uri: &http::Uri) -> bool {
+        uri == key.uri_string.as_str()
+    }
+
+    fn key_uri(key: &Self::Key) -> &str {
+        &key.uri_string
+    }
+
+    fn uri_string(&self, uri: &http::Uri) -> String {
+        uri.to_string()
+    }
+
+    fn matches_path_prefix(&self, key: &Self::Key, prefix: &str) -> bool {
+        uri_path_starts_with(&key.uri_string, prefix)
+    }
+
+    fn key_heap_size(key: &Self::Key) -> usize {
+        let header_values_size: usize = [&key.accept, &key.accept_encoding, &key.accept_language]
+            .into_iter()
+            .flatten()
+            .map(|hv| size_of::<HeaderValue>() + hv.len())
+            .sum();
+        key.uri_string.len() + header_values_size
+    }
+}
+
 fn calc_header_key_component(headers: &HeaderMap, name: HeaderName) -> Vec<HeaderValue> {
     let mut res: Vec<_> = headers.get_all(name).iter().cloned().collect();
     res.sort_unstable();
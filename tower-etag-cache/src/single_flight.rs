@@ -0,0 +1,43 @@
+use std::{
+    any::Any,
+    fmt::{self, Debug},
+    sync::Arc,
+};
+
+/// `http::Request` extension that asks a [`CacheProvider`](crate::CacheProvider)'s cache-lookup `Service`
+/// to coalesce concurrent misses for the request's cache key.
+///
+/// Inserted by [`EtagCache`](crate::EtagCache) if [`EtagCache::single_flight`](crate::EtagCache::single_flight) is enabled.
+/// Providers that do not support coalescing may ignore it.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct SingleFlightRequest;
+
+/// `http::Request` extension inserted by a [`CacheProvider`](crate::CacheProvider)'s cache-lookup `Service`
+/// into the [`SingleFlightRequest`] whose miss other requests for the same cache key wait on.
+///
+/// [`EtagCache`](crate::EtagCache) passes it to the cache-put `Service` as a response extension,
+/// and drops it once that `Service` has resolved if the provider did not take it out.
+/// Providers that save the ETag later, e.g. at the end of a streamed body, should hold it until then.
+/// Once it and all its clones are dropped, the value it was created with is dropped,
+/// which should wake the waiting requests so that they look up the cache again.
+#[derive(Clone)]
+pub struct SingleFlightGuard {
+    /// Only held to be dropped
+    _on_release: Arc<dyn Any + Send + Sync>,
+}
+
+impl SingleFlightGuard {
+    /// `on_release` should wake the waiting requests when dropped
+    pub fn new<T: Send + Sync + 'static>(on_release: T) -> Self {
+        Self {
+            _on_release: Arc::new(on_release),
+        }
+    }
+}
+
+// manual impl since the wrapped value is not required to be Debug
+impl Debug for SingleFlightGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SingleFlightGuard").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,33 @@
+use http::{HeaderMap, HeaderName};
+
+/// `http::Response` extension listing the tags of the response's cache entry,
+/// e.g. the ids of the database records the response was built from.
+///
+/// Built-in [`CacheProvider`](crate::CacheProvider)s index entries by tag
+/// so that [`PurgeRequest::Tag`](crate::PurgeRequest::Tag) removes every entry carrying a tag.
+///
+/// Can be inserted by the inner service, or by [`EtagCache`](crate::EtagCache)
+/// from a response header, see [`EtagCache::tag_header`](crate::EtagCache::tag_header).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CacheTags(pub Vec<String>);
+
+impl CacheTags {
+    pub fn new<T: Into<String>>(tags: impl IntoIterator<Item = T>) -> Self {
+        Self(tags.into_iter().map(Into::into).collect())
+    }
+
+    /// Adds the tags of all `name` header values, separated by whitespace or commas
+    /// like `Surrogate-Key` and `Cache-Tag` values.
+    ///
+    /// Non-ascii values are ignored.
+    pub fn extend_from_header(&mut self, headers: &HeaderMap, name: &HeaderName) {
+        let tags = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|hv| hv.to_str().ok())
+            .flat_map(|s| s.split(|c: char| c == ',' || c.is_ascii_whitespace()))
+            .filter(|tag| !tag.is_empty())
+            .map(String::from);
+        self.0.extend(tags);
+    }
+}
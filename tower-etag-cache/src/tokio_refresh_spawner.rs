@@ -0,0 +1,18 @@
+//! [`RefreshSpawner`] that spawns refreshes onto the current tokio runtime
+
+use std::future::Future;
+
+use crate::RefreshSpawner;
+
+/// Spawns refreshes with `tokio::spawn`, so [`EtagCache`](crate::EtagCache) must be called within a tokio runtime
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct TokioRefreshSpawner;
+
+impl<Fut: Future + Send + 'static> RefreshSpawner<Fut> for TokioRefreshSpawner {
+    fn spawn(&self, fut: Fut) {
+        tokio::spawn(async move {
+            // errors and the response are of no use to anyone
+            let _ = fut.await;
+        });
+    }
+}
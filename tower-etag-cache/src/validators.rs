@@ -0,0 +1,387 @@
+use http::{
+    header::{IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE},
+    HeaderMap, HeaderName, Method,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{EntityTag, EntityTagCondition};
+
+/// Outcome of evaluating a request's conditional headers against [`Validators`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PreconditionOutcome {
+    /// Preconditions passed or were not present. Proceed to process the request normally.
+    Proceed,
+
+    /// Respond with HTTP 304 Not Modified
+    NotModified,
+
+    /// Respond with HTTP 412 Precondition Failed
+    PreconditionFailed,
+}
+
+/// The validators of a cached representation that
+/// a request's conditional headers are evaluated against
+#[derive(Debug, Clone, Copy)]
+pub struct Validators<'a> {
+    pub etag: &'a EntityTag,
+
+    /// `None` if the cache does not track modification times
+    pub last_modified: Option<SystemTime>,
+}
+
+impl<'a> Validators<'a> {
+    /// Evaluates `req`'s conditional headers in the order given by
+    /// [RFC 9110 section 13.2.2](https://www.rfc-editor.org/rfc/rfc9110#section-13.2.2):
+    /// 1. If `If-Match` is present and does not strongly match `etag`, the precondition fails.
+    /// 2. Else if `If-Unmodified-Since` is present and `last_modified` is newer than it, the precondition fails.
+    /// 3. If `If-None-Match` is present and weakly matches `etag`, `GET` and `HEAD` requests are not modified
+    ///    while preconditions fail for other methods.
+    /// 4. Else if `If-None-Match` is absent, the method is `GET` or `HEAD` and `last_modified` is
+    ///    not newer than `If-Modified-Since`, the request is not modified.
+    ///
+    /// A malformed `If-Match` fails the precondition, a malformed `If-None-Match` is treated as not matching.
+    /// Date headers that are not a single valid HTTP-date are ignored.
+    pub fn evaluate<T>(&self, req: &http::Request<T>) -> PreconditionOutcome {
+        Self::evaluate_any(std::slice::from_ref(self), req)
+    }
+
+    /// Evaluates `req`'s conditional headers like [`Self::evaluate`], but against the validators
+    /// of all current representations of the target resource, e.g. those selected by different `Accept` headers:
+    /// - `If-Match` and `If-None-Match` match if they match any representation's ETag
+    /// - `If-Unmodified-Since` fails if any representation was modified after it
+    /// - `If-Modified-Since` is only not modified if no representation was modified after it
+    ///
+    /// If `variants` is empty, `If-Match` always fails and `If-None-Match` never matches.
+    pub fn evaluate_any<T>(variants: &[Self], req: &http::Request<T>) -> PreconditionOutcome {
+        let headers = req.headers();
+        let is_get_or_head = matches!(*req.method(), Method::GET | Method::HEAD);
+
+        match EntityTagCondition::from_headers(headers, IF_MATCH) {
+            Ok(Some(if_match)) => {
+                if !variants.iter().any(|v| if_match.matches_strong(v.etag)) {
+                    return PreconditionOutcome::PreconditionFailed;
+                }
+            }
+            Ok(None) => {
+                if let Some(if_unmodified_since) = single_http_date(headers, IF_UNMODIFIED_SINCE) {
+                    if variants
+                        .iter()
+                        .any(|v| v.is_modified_since(if_unmodified_since) == Some(true))
+                    {
+                        return PreconditionOutcome::PreconditionFailed;
+                    }
+                }
+            }
+            Err(_) => return PreconditionOutcome::PreconditionFailed,
+        }
+
+        match EntityTagCondition::from_headers(headers, IF_NONE_MATCH) {
+            Ok(Some(if_none_match)) => {
+                let matches = variants.iter().any(|v| if_none_match.matches_weak(v.etag));
+                return match (matches, is_get_or_head) {
+                    (false, _) => PreconditionOutcome::Proceed,
+                    (true, true) => PreconditionOutcome::NotModified,
+                    (true, false) => PreconditionOutcome::PreconditionFailed,
+                };
+            }
+            Ok(None) => (),
+            Err(_) => return PreconditionOutcome::Proceed,
+        }
+
+        if !is_get_or_head {
+            return PreconditionOutcome::Proceed;
+        }
+        match single_http_date(headers, IF_MODIFIED_SINCE) {
+            Some(if_modified_since)
+                if !variants.is_empty()
+                    && variants
+                        .iter()
+                        .all(|v| v.is_modified_since(if_modified_since) == Some(false)) =>
+            {
+                PreconditionOutcome::NotModified
+            }
+            _ => PreconditionOutcome::Proceed,
+        }
+    }
+
+    /// `None` if `last_modified` is not tracked
+    fn is_modified_since(&self, date: SystemTime) -> Option<bool> {
+        self.last_modified
+            .map(|last_modified| unix_secs(last_modified) > unix_secs(date))
+    }
+}
+
+/// Returns true if `headers` contains any of the conditional request headers evaluated by [`Validators`]
+pub(crate) fn has_conditional_headers(headers: &HeaderMap) -> bool {
+    [
+        IF_MATCH,
+        IF_UNMODIFIED_SINCE,
+        IF_NONE_MATCH,
+        IF_MODIFIED_SINCE,
+    ]
+    .iter()
+    .any(|name| headers.contains_key(name))
+}
+
+/// Returns `None` if the header is absent, has more than one member or is not a valid HTTP-date
+fn single_http_date(headers: &HeaderMap, name: HeaderName) -> Option<SystemTime> {
+    let mut iter = headers.get_all(name).into_iter();
+    let hv = match (iter.next(), iter.next()) {
+        (Some(hv), None) => hv,
+        _ => return None,
+    };
+    httpdate::parse_http_date(hv.to_str().ok()?).ok()
+}
+
+/// HTTP-dates have second precision
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use PreconditionOutcome::{NotModified, PreconditionFailed, Proceed};
+
+    const EARLY: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+    const LATE: &str = "Mon, 07 Nov 1994 08:49:37 GMT";
+
+    fn req(method: Method, headers: &[(HeaderName, &str)]) -> http::Request<()> {
+        let mut builder = http::Request::builder().method(method);
+        for (name, value) in headers {
+            builder = builder.header(name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn evaluate(method: Method, headers: &[(HeaderName, &str)]) -> PreconditionOutcome {
+        let etag = EntityTag::strong("a").unwrap();
+        let validators = Validators {
+            etag: &etag,
+            // between EARLY and LATE
+            last_modified: Some(
+                httpdate::parse_http_date(EARLY).unwrap() + Duration::from_secs(60),
+            ),
+        };
+        validators.evaluate(&req(method, headers))
+    }
+
+    #[test]
+    fn no_conditional_headers() {
+        assert_eq!(evaluate(Method::GET, &[]), Proceed);
+        assert_eq!(evaluate(Method::PUT, &[]), Proceed);
+    }
+
+    #[test]
+    fn if_match() {
+        assert_eq!(evaluate(Method::PUT, &[(IF_MATCH, "\"a\"")]), Proceed);
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_MATCH, "\"b\", \"a\"")]),
+            Proceed
+        );
+        assert_eq!(evaluate(Method::PUT, &[(IF_MATCH, "*")]), Proceed);
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_MATCH, "\"b\"")]),
+            PreconditionFailed
+        );
+        // strong comparison
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_MATCH, "W/\"a\"")]),
+            PreconditionFailed
+        );
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_MATCH, "a")]),
+            PreconditionFailed
+        );
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert_eq!(
+            evaluate(Method::GET, &[(IF_NONE_MATCH, "\"a\"")]),
+            NotModified
+        );
+        assert_eq!(evaluate(Method::HEAD, &[(IF_NONE_MATCH, "*")]), NotModified);
+        // weak comparison
+        assert_eq!(
+            evaluate(Method::GET, &[(IF_NONE_MATCH, "W/\"b\", W/\"a\"")]),
+            NotModified
+        );
+        assert_eq!(evaluate(Method::GET, &[(IF_NONE_MATCH, "\"b\"")]), Proceed);
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_NONE_MATCH, "W/\"a\"")]),
+            PreconditionFailed
+        );
+        assert_eq!(evaluate(Method::PUT, &[(IF_NONE_MATCH, "\"b\"")]), Proceed);
+        // malformed is treated as not matching
+        assert_eq!(evaluate(Method::GET, &[(IF_NONE_MATCH, "a")]), Proceed);
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_UNMODIFIED_SINCE, LATE)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_UNMODIFIED_SINCE, EARLY)]),
+            PreconditionFailed
+        );
+        // invalid dates are ignored
+        assert_eq!(
+            evaluate(Method::PUT, &[(IF_UNMODIFIED_SINCE, "yesterday")]),
+            Proceed
+        );
+    }
+
+    #[test]
+    fn if_modified_since() {
+        assert_eq!(
+            evaluate(Method::GET, &[(IF_MODIFIED_SINCE, LATE)]),
+            NotModified
+        );
+        assert_eq!(
+            evaluate(Method::GET, &[(IF_MODIFIED_SINCE, EARLY)]),
+            Proceed
+        );
+        // only applies to GET and HEAD
+        assert_eq!(
+            evaluate(Method::POST, &[(IF_MODIFIED_SINCE, LATE)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate(Method::GET, &[(IF_MODIFIED_SINCE, "yesterday")]),
+            Proceed
+        );
+    }
+
+    #[test]
+    fn without_last_modified_dates_are_ignored() {
+        let etag = EntityTag::strong("a").unwrap();
+        let validators = Validators {
+            etag: &etag,
+            last_modified: None,
+        };
+        assert_eq!(
+            validators.evaluate(&req(Method::GET, &[(IF_MODIFIED_SINCE, LATE)])),
+            Proceed
+        );
+        assert_eq!(
+            validators.evaluate(&req(Method::PUT, &[(IF_UNMODIFIED_SINCE, EARLY)])),
+            Proceed
+        );
+    }
+
+    /// RFC 9110 section 13.2.2
+    #[test]
+    fn precedence() {
+        // If-Match is evaluated first and overrides If-Unmodified-Since
+        assert_eq!(
+            evaluate(
+                Method::PUT,
+                &[(IF_MATCH, "\"a\""), (IF_UNMODIFIED_SINCE, EARLY)]
+            ),
+            Proceed
+        );
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[(IF_MATCH, "\"b\""), (IF_NONE_MATCH, "\"a\"")]
+            ),
+            PreconditionFailed
+        );
+        // If-Unmodified-Since is evaluated before If-None-Match
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[(IF_UNMODIFIED_SINCE, EARLY), (IF_NONE_MATCH, "\"a\"")]
+            ),
+            PreconditionFailed
+        );
+        // If-None-Match overrides If-Modified-Since
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[(IF_NONE_MATCH, "\"b\""), (IF_MODIFIED_SINCE, LATE)]
+            ),
+            Proceed
+        );
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[(IF_NONE_MATCH, "\"a\""), (IF_MODIFIED_SINCE, EARLY)]
+            ),
+            NotModified
+        );
+        // all passing
+        assert_eq!(
+            evaluate(
+                Method::GET,
+                &[
+                    (IF_MATCH, "\"a\""),
+                    (IF_UNMODIFIED_SINCE, LATE),
+                    (IF_NONE_MATCH, "\"a\""),
+                ]
+            ),
+            NotModified
+        );
+    }
+
+    #[test]
+    fn evaluate_any_variant() {
+        let html = EntityTag::strong("html").unwrap();
+        let json = EntityTag::strong("json").unwrap();
+        let early = httpdate::parse_http_date(EARLY).unwrap();
+        let late = httpdate::parse_http_date(LATE).unwrap();
+        let variants = [
+            Validators {
+                etag: &html,
+                last_modified: Some(early),
+            },
+            Validators {
+                etag: &json,
+                last_modified: Some(late),
+            },
+        ];
+        let evaluate_any = |method, headers: &[(HeaderName, &str)]| {
+            Validators::evaluate_any(&variants, &req(method, headers))
+        };
+
+        assert_eq!(
+            evaluate_any(Method::PUT, &[(IF_MATCH, "\"json\"")]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_any(Method::PUT, &[(IF_MATCH, "\"xml\"")]),
+            PreconditionFailed
+        );
+        assert_eq!(
+            evaluate_any(Method::PUT, &[(IF_NONE_MATCH, "\"html\"")]),
+            PreconditionFailed
+        );
+        assert_eq!(
+            evaluate_any(Method::PUT, &[(IF_UNMODIFIED_SINCE, EARLY)]),
+            PreconditionFailed
+        );
+        assert_eq!(
+            evaluate_any(Method::PUT, &[(IF_UNMODIFIED_SINCE, LATE)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_any(Method::GET, &[(IF_MODIFIED_SINCE, EARLY)]),
+            Proceed
+        );
+        assert_eq!(
+            evaluate_any(Method::GET, &[(IF_MODIFIED_SINCE, LATE)]),
+            NotModified
+        );
+
+        let none = |headers: &[(HeaderName, &str)]| {
+            Validators::evaluate_any(&[], &req(Method::PUT, headers))
+        };
+        assert_eq!(none(&[(IF_MATCH, "*")]), PreconditionFailed);
+        assert_eq!(none(&[(IF_NONE_MATCH, "*")]), Proceed);
+    }
+}
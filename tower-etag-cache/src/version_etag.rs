@@ -0,0 +1,125 @@
+//! [`EtagGenerator`] that derives each [`EntityTag`] from an app-supplied content version instead of hashing the body
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use http::header::{CONTENT_ENCODING, CONTENT_LANGUAGE, CONTENT_TYPE};
+
+use crate::{EntityTag, EtagGenerator, EtagHasher};
+
+/// `http::Response` extension inserted by the app with the version of the response's content,
+/// e.g. a database row version or a revision number, for [`VersionEtagGenerator`].
+///
+/// The version must change whenever the body changes. Variants of the same resource with different
+/// `Content-Type`, `Content-Encoding` or `Content-Language` get different ETags for the same version,
+/// but variants that only differ in their body, e.g. by a request header only named in `Vary`,
+/// must be given different versions.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, PartialOrd, Ord, Hash)]
+pub struct ContentVersion(pub u64);
+
+/// Generates strong ETags from the response's [`ContentVersion`] without reading its body.
+///
+/// The ETag is a function of only the version and the response's `Content-Type`, `Content-Encoding`
+/// and `Content-Language`, so it only changes when the app changes the version:
+/// cache misses, evictions, refreshes and restarts all reproduce the same ETag.
+/// It is therefore only as correct as the versions the app supplies, see [`ContentVersion`].
+///
+/// Responses without a [`ContentVersion`] have their body hashed together with those headers instead,
+/// using std's `DefaultHasher`, a fast non-cryptographic 64-bit hash whose output may change
+/// between Rust releases.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct VersionEtagGenerator;
+
+impl EtagGenerator for VersionEtagGenerator {
+    type Hasher = VersionEtagHasher;
+
+    fn hasher(&self, parts: &http::response::Parts) -> Self::Hasher {
+        let mut hasher = DefaultHasher::new();
+        for name in [CONTENT_TYPE, CONTENT_ENCODING, CONTENT_LANGUAGE] {
+            for value in parts.headers.get_all(name) {
+                hasher.write(value.as_bytes());
+                // not a valid header value byte, separates values
+                hasher.write_u8(b'\n');
+            }
+            hasher.write_u8(0);
+        }
+        VersionEtagHasher {
+            hasher,
+            version: parts.extensions.get::<ContentVersion>().map(|v| v.0),
+        }
+    }
+}
+
+/// Ignores the body if the response has a [`ContentVersion`]
+#[derive(Clone, Debug)]
+pub struct VersionEtagHasher {
+    /// Already fed the representation headers
+    hasher: DefaultHasher,
+    version: Option<u64>,
+}
+
+impl EtagHasher for VersionEtagHasher {
+    fn update(&mut self, data: &[u8]) {
+        if self.version.is_none() {
+            self.hasher.write(data);
+        }
+    }
+
+    fn finish(&mut self) -> EntityTag {
+        let hash = self.hasher.finish();
+        let tag = match self.version {
+            Some(version) => format!("v{version:x}-{hash:x}"),
+            None => format!("h{hash:x}"),
+        };
+        // unwrap-safety: alphanumerics and '-' are all valid etagc
+        EntityTag::strong(tag).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn parts(version: Option<u64>, content_type: &'static str) -> http::response::Parts {
+        let (mut parts, ()) = http::Response::new(()).into_parts();
+        parts
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        if let Some(v) = version {
+            parts.extensions.insert(ContentVersion(v));
+        }
+        parts
+    }
+
+    #[test]
+    fn stable_across_generators() {
+        let a = VersionEtagGenerator.generate(&parts(Some(7), "text/html"), b"a");
+        let b = VersionEtagGenerator.generate(&parts(Some(7), "text/html"), b"b");
+        assert_eq!(a, b);
+        assert!(!a.is_weak());
+        assert!(a.opaque_tag().starts_with("v7-"));
+    }
+
+    #[test]
+    fn changes_with_version_and_representation() {
+        let html = VersionEtagGenerator.generate(&parts(Some(7), "text/html"), b"");
+        let next = VersionEtagGenerator.generate(&parts(Some(8), "text/html"), b"");
+        let json = VersionEtagGenerator.generate(&parts(Some(7), "application/json"), b"");
+        assert_ne!(html, next);
+        assert_ne!(html, json);
+    }
+
+    #[test]
+    fn hashes_body_without_version() {
+        let p = parts(None, "text/html");
+        let whole = VersionEtagGenerator.generate(&p, b"hello world");
+        let mut hasher = VersionEtagGenerator.hasher(&p);
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        assert_eq!(hasher.finish(), whole);
+        assert!(whole.opaque_tag().starts_with('h'));
+        assert_ne!(VersionEtagGenerator.generate(&p, b"hello"), whole);
+    }
+}
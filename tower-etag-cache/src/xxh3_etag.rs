@@ -0,0 +1,32 @@
+//! [`EtagGenerator`] that calculates an [`EntityTag`] as the hex-encoded 128-bit xxh3 hash of the response body
+
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::{EntityTag, EtagGenerator, EtagHasher};
+
+/// Calculates strong ETags as the hex-encoded 128-bit xxh3 hash of the body bytes.
+///
+/// Much faster than the cryptographic hashes, but should not be used
+/// if clients can deliberately craft colliding response bodies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Xxh3EtagGenerator;
+
+impl EtagGenerator for Xxh3EtagGenerator {
+    /// Boxed since Xxh3 is large
+    type Hasher = Box<Xxh3>;
+
+    fn hasher(&self, _parts: &http::response::Parts) -> Self::Hasher {
+        Box::default()
+    }
+}
+
+impl EtagHasher for Box<Xxh3> {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+
+    fn finish(&mut self) -> EntityTag {
+        // unwrap-safety: hex digits are all valid etagc
+        EntityTag::strong(format!("{:032x}", self.digest128())).unwrap()
+    }
+}
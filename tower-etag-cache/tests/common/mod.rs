@@ -0,0 +1,48 @@
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use http::{Request, Response};
+use http_body::Body;
+use http_body_util::{BodyExt, Full};
+use std::{convert::Infallible, fmt::Debug, future::ready};
+use tower::{service_fn, util::BoxCloneService, Service, ServiceExt};
+
+pub type App = BoxCloneService<Request<Full<Bytes>>, Response<Full<Bytes>>, Infallible>;
+
+/// Inner service that responds to every request with `f`
+pub fn app(f: fn(&Request<Full<Bytes>>) -> Response<Full<Bytes>>) -> App {
+    BoxCloneService::new(service_fn(move |req| ready(Ok(f(&req)))))
+}
+
+pub fn req(method: &str, uri: &str, headers: &[(&str, &str)]) -> Request<Full<Bytes>> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    builder.body(Full::default()).unwrap()
+}
+
+/// Returns the response without polling its body
+pub async fn call<S, B>(svc: &mut S, req: Request<Full<Bytes>>) -> Response<B>
+where
+    S: Service<Request<Full<Bytes>>, Response = Response<B>>,
+    S::Error: Debug,
+{
+    svc.ready().await.unwrap().call(req).await.unwrap()
+}
+
+pub async fn send<S, B>(svc: &mut S, req: Request<Full<Bytes>>) -> (http::response::Parts, Bytes)
+where
+    S: Service<Request<Full<Bytes>>, Response = Response<B>>,
+    S::Error: Debug,
+    B: Body,
+    B::Error: Debug,
+{
+    let (parts, body) = call(svc, req).await.into_parts();
+    (parts, body.collect().await.unwrap().to_bytes())
+}
+
+/// The `ETag` response header
+pub fn etag(parts: &http::response::Parts) -> &str {
+    parts.headers["etag"].to_str().unwrap()
+}
@@ -0,0 +1,139 @@
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::Full;
+use std::{
+    fmt::Debug,
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tower::Service;
+use tower_etag_cache::{
+    const_lru_provider::{ConstLruProvider, ConstLruProviderConfig},
+    shared_lru_provider::SharedLruProvider,
+    EtagCacheLayer,
+};
+use tower_layer::Layer;
+
+mod common;
+
+use common::{app, etag, req, send, App};
+
+fn with_header(name: &'static str, value: &'static str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .header(name, value)
+        .body(Full::from("hello"))
+        .unwrap()
+}
+
+fn max_age_0(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    with_header("cache-control", "max-age=0")
+}
+
+fn invalid_expires(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    with_header("expires", "0")
+}
+
+fn max_age_1(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    with_header("cache-control", "max-age=1")
+}
+
+/// Returns the status of a conditional request with the ETag of a first request
+async fn revalidate<S, B>(mut svc: S, wait: Duration) -> StatusCode
+where
+    S: Service<Request<Full<Bytes>>, Response = Response<B>>,
+    S::Error: Debug,
+    B: Body,
+    B::Error: Debug,
+{
+    let (parts, _) = send(&mut svc, req("GET", "/", &[])).await;
+    let etag = etag(&parts).to_owned();
+    tokio::time::sleep(wait).await;
+    let (parts, _) = send(&mut svc, req("GET", "/", &[("if-none-match", &etag)])).await;
+    parts.status
+}
+
+fn const_lru(
+    inner: App,
+    use_freshness_lifetime: bool,
+) -> impl Service<
+    Request<Full<Bytes>>,
+    Response = Response<impl Body<Error = impl Debug>>,
+    Error = impl Debug,
+> {
+    let config = ConstLruProviderConfig {
+        use_freshness_lifetime,
+        ..Default::default()
+    };
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, config);
+    EtagCacheLayer::with_default_predicate(provider).layer(inner)
+}
+
+fn shared_lru(
+    inner: App,
+    use_freshness_lifetime: bool,
+) -> impl Service<
+    Request<Full<Bytes>>,
+    Response = Response<impl Body<Error = impl Debug>>,
+    Error = impl Debug,
+> {
+    let provider = SharedLruProvider::new(NonZeroUsize::new(8).unwrap())
+        .use_freshness_lifetime(use_freshness_lifetime);
+    EtagCacheLayer::with_default_predicate(provider).layer(inner)
+}
+
+#[tokio::test]
+async fn zero_lifetime_keeps_etag() {
+    for inner in [max_age_0, invalid_expires] {
+        assert_eq!(
+            revalidate(const_lru(app(inner), true), Duration::ZERO).await,
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            revalidate(shared_lru(app(inner), true), Duration::ZERO).await,
+            StatusCode::NOT_MODIFIED
+        );
+    }
+}
+
+#[tokio::test]
+async fn freshness_lifetime_is_opt_in() {
+    let wait = Duration::from_millis(1100);
+    let statuses = tokio::join!(
+        revalidate(const_lru(app(max_age_1), false), wait),
+        revalidate(shared_lru(app(max_age_1), false), wait),
+        revalidate(const_lru(app(max_age_1), true), wait),
+        revalidate(shared_lru(app(max_age_1), true), wait),
+    );
+    assert_eq!(
+        statuses,
+        (
+            StatusCode::NOT_MODIFIED,
+            StatusCode::NOT_MODIFIED,
+            StatusCode::OK,
+            StatusCode::OK
+        )
+    );
+}
+
+#[tokio::test]
+async fn zero_lifetime_full_response_not_saved() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn counted(req: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        max_age_0(req)
+    }
+
+    let config = ConstLruProviderConfig {
+        full_response_headers: Some(ConstLruProviderConfig::DEFAULT_FULL_RESPONSE_HEADERS),
+        ..Default::default()
+    };
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, config);
+    let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(counted));
+    send(&mut svc, req("GET", "/", &[])).await;
+    let (parts, body) = send(&mut svc, req("GET", "/", &[])).await;
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body, "hello");
+    assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+}
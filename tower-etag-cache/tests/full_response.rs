@@ -0,0 +1,128 @@
+use bytes::Bytes;
+use http::{header::COOKIE, Request, Response, StatusCode};
+use http_body_util::Full;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tower_etag_cache::{
+    base64_blake3_body_etag::Base64Blake3EtagGenerator,
+    configurable_etag_cache_key::ConfigurableEtagCacheKeyExtractor,
+    const_lru_provider::{ConstLruProvider, ConstLruProviderConfig},
+    EtagCacheLayer,
+};
+use tower_layer::Layer;
+
+mod common;
+
+use common::{app, req, send};
+
+fn full_responses() -> ConstLruProviderConfig {
+    ConstLruProviderConfig {
+        full_response_headers: Some(ConstLruProviderConfig::DEFAULT_FULL_RESPONSE_HEADERS),
+        ..Default::default()
+    }
+}
+
+/// Greets the request's cookie
+fn greet(req: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    let cookie = req
+        .headers()
+        .get(COOKIE)
+        .map_or("", |v| v.to_str().unwrap());
+    Response::builder()
+        .header("vary", "cookie")
+        .body(Full::from(format!("hello {cookie}")))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn cookies_not_in_key_are_not_shared() {
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, full_responses());
+    let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(greet));
+
+    let (_, body) = send(&mut svc, req("GET", "/", &[("cookie", "session=alice")])).await;
+    assert_eq!(body, "hello session=alice");
+    let (_, body) = send(&mut svc, req("GET", "/", &[("cookie", "session=bob")])).await;
+    assert_eq!(body, "hello session=bob");
+}
+
+#[tokio::test]
+async fn cookies_in_key_are_saved_per_cookie() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn counted(req: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        greet(req)
+    }
+
+    let provider = ConstLruProvider::<_, _, 8, usize, _>::init_with_key_extractor(
+        4,
+        full_responses(),
+        Base64Blake3EtagGenerator,
+        ConfigurableEtagCacheKeyExtractor::default().with_header(COOKIE),
+    );
+    let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(counted));
+
+    for _ in 0..2 {
+        for user in ["alice", "bob"] {
+            let cookie = format!("session={user}");
+            let (_, body) = send(&mut svc, req("GET", "/", &[("cookie", &cookie)])).await;
+            assert_eq!(body, format!("hello {cookie}"));
+        }
+    }
+    assert_eq!(CALLS.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn authorized_requests_are_not_served_saved_responses() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn counted(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        let calls = CALLS.fetch_add(1, Ordering::Relaxed);
+        Response::new(Full::from(format!("call {calls}")))
+    }
+
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, full_responses());
+    let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(counted));
+
+    // not saved
+    let (_, body) = send(&mut svc, req("GET", "/", &[("authorization", "Bearer a")])).await;
+    assert_eq!(body, "call 0");
+    let (_, body) = send(&mut svc, req("GET", "/", &[])).await;
+    assert_eq!(body, "call 1");
+    // saved, but not served to authorized requests
+    let (_, body) = send(&mut svc, req("GET", "/", &[])).await;
+    assert_eq!(body, "call 1");
+    let (_, body) = send(&mut svc, req("GET", "/", &[("authorization", "Bearer a")])).await;
+    assert_eq!(body, "call 2");
+}
+
+#[tokio::test]
+async fn unshareable_responses_are_not_saved() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn vary_any(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        Response::builder()
+            .header("vary", "*")
+            .body(Full::from("hello"))
+            .unwrap()
+    }
+    static NO_CACHE_CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn no_cache(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        NO_CACHE_CALLS.fetch_add(1, Ordering::Relaxed);
+        Response::builder()
+            .header("cache-control", "No-Cache")
+            .body(Full::from("hello"))
+            .unwrap()
+    }
+
+    for (f, calls) in [
+        (vary_any as fn(&_) -> _, &CALLS),
+        (no_cache as fn(&_) -> _, &NO_CACHE_CALLS),
+    ] {
+        let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, full_responses());
+        let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(f));
+        for _ in 0..2 {
+            let (parts, body) = send(&mut svc, req("GET", "/", &[])).await;
+            assert_eq!(parts.status, StatusCode::OK);
+            assert_eq!(body, "hello");
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,121 @@
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use http_body_util::Full;
+use std::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tower_etag_cache::{
+    const_lru_provider::{ConstLruProvider, ConstLruProviderConfig},
+    shared_lru_provider::SharedLruProvider,
+    EtagCacheLayer,
+};
+use tower_layer::Layer;
+
+mod common;
+
+use common::{app, call, etag, req, send};
+
+fn hello(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    Response::new(Full::from("hello"))
+}
+
+/// Streams every body
+fn stream_all() -> ConstLruProviderConfig {
+    ConstLruProviderConfig {
+        stream_threshold: Some(0),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn const_lru_streamed_responses_have_no_etag() {
+    static VERSION: AtomicUsize = AtomicUsize::new(0);
+    fn versioned(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        let version = VERSION.fetch_add(1, Ordering::Relaxed);
+        Response::new(Full::from(format!("version {version}")))
+    }
+
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, stream_all());
+    let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(versioned));
+
+    let (parts, body) = send(&mut svc, req("GET", "/", &[])).await;
+    assert_eq!(body, "version 0");
+    assert!(!parts.headers.contains_key("etag"));
+    assert!(!parts.headers.contains_key("last-modified"));
+    assert!(parts.headers.contains_key("vary"));
+
+    // the ETag saved for the previous body must not be sent with the changed one
+    let (parts, body) = send(&mut svc, req("GET", "/", &[])).await;
+    assert_eq!(body, "version 1");
+    assert!(!parts.headers.contains_key("etag"));
+
+    // HEAD and conditional requests use the saved ETag of the last body
+    let (parts, _) = send(&mut svc, req("HEAD", "/", &[])).await;
+    let saved = etag(&parts).to_owned();
+    let (parts, _) = send(&mut svc, req("GET", "/", &[("if-none-match", &saved)])).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn const_lru_head_fetches_get_hashes_unpolled_streamed_body() {
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, stream_all());
+    let mut svc = EtagCacheLayer::with_default_predicate(provider)
+        .head_fetches_get(true)
+        .layer(app(hello));
+
+    // body is never polled, like hyper does for HEAD responses
+    let resp = call(&mut svc, req("HEAD", "/", &[])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let head_etag = etag(&resp.into_parts().0).to_owned();
+
+    let (parts, _) = send(&mut svc, req("GET", "/", &[("if-none-match", &head_etag)])).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn const_lru_head_fetches_get_buffered() {
+    let provider = ConstLruProvider::<_, _, 8>::init(4);
+    let mut svc = EtagCacheLayer::with_default_predicate(provider)
+        .head_fetches_get(true)
+        .layer(app(hello));
+
+    let resp = call(&mut svc, req("HEAD", "/", &[])).await;
+    let head_etag = etag(&resp.into_parts().0).to_owned();
+    let (parts, body) = send(&mut svc, req("GET", "/", &[])).await;
+    assert_eq!(etag(&parts), head_etag);
+    assert_eq!(body, "hello");
+}
+
+#[tokio::test]
+async fn shared_lru_head_uses_get_etag() {
+    let provider = SharedLruProvider::new(NonZeroUsize::new(8).unwrap());
+    let mut svc = EtagCacheLayer::with_default_predicate(provider).layer(app(hello));
+
+    // nothing saved yet
+    let (parts, _) = send(&mut svc, req("HEAD", "/", &[])).await;
+    assert!(!parts.headers.contains_key("etag"));
+
+    let (parts, _) = send(&mut svc, req("GET", "/", &[])).await;
+    let get_etag = etag(&parts).to_owned();
+
+    let (parts, _) = send(&mut svc, req("HEAD", "/", &[])).await;
+    assert_eq!(etag(&parts), get_etag);
+
+    let (parts, _) = send(&mut svc, req("HEAD", "/", &[("if-none-match", &get_etag)])).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+}
+
+#[tokio::test]
+async fn shared_lru_head_fetches_get() {
+    let provider = SharedLruProvider::new(NonZeroUsize::new(8).unwrap());
+    let mut svc = EtagCacheLayer::with_default_predicate(provider)
+        .head_fetches_get(true)
+        .layer(app(hello));
+
+    let resp = call(&mut svc, req("HEAD", "/", &[])).await;
+    let head_etag = etag(&resp.into_parts().0).to_owned();
+
+    let (parts, _) = send(&mut svc, req("GET", "/", &[("if-none-match", &head_etag)])).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+}
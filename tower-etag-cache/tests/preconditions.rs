@@ -0,0 +1,65 @@
+use bytes::Bytes;
+use http::{header::ACCEPT, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::Full;
+use std::{fmt::Debug, num::NonZeroUsize};
+use tower::Service;
+use tower_etag_cache::{
+    const_lru_provider::ConstLruProvider, shared_lru_provider::SharedLruProvider, EtagCacheLayer,
+};
+use tower_layer::Layer;
+
+mod common;
+
+use common::{app, etag, req, send};
+
+fn negotiated(req: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    if req.method() != "GET" {
+        return Response::new(Full::from("updated"));
+    }
+    let body = match req.headers().get(ACCEPT).map(|v| v.as_bytes()) {
+        Some(b"text/html") => "<p>hello</p>",
+        _ => r#"{"hello":true}"#,
+    };
+    Response::new(Full::from(body))
+}
+
+/// The GET varies by `Accept`, the PUT's preconditions must still be evaluated against it
+async fn accept_differs<S, B>(mut svc: S)
+where
+    S: Service<Request<Full<Bytes>>, Response = Response<B>>,
+    S::Error: Debug,
+    B: Body,
+    B::Error: Debug,
+{
+    let (parts, _) = send(&mut svc, req("GET", "/doc", &[("accept", "text/html")])).await;
+    let html_etag = etag(&parts).to_owned();
+
+    let (parts, _) = send(&mut svc, req("PUT", "/doc", &[("if-match", "\"stale\"")])).await;
+    assert_eq!(parts.status, StatusCode::PRECONDITION_FAILED);
+
+    let (parts, _) = send(&mut svc, req("PUT", "/doc", &[("if-none-match", "*")])).await;
+    assert_eq!(parts.status, StatusCode::PRECONDITION_FAILED);
+
+    let (parts, body) = send(&mut svc, req("PUT", "/doc", &[("if-match", &html_etag)])).await;
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(body, "updated");
+
+    // nothing cached for the URI, left to the inner service
+    let (parts, _) = send(&mut svc, req("PUT", "/other", &[("if-match", "\"stale\"")])).await;
+    assert_eq!(parts.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn const_lru_accept_differs() {
+    let provider = ConstLruProvider::<_, _, 8>::init(4);
+    let layer = EtagCacheLayer::with_default_predicate(provider).enforce_preconditions(true);
+    accept_differs(layer.layer(app(negotiated))).await;
+}
+
+#[tokio::test]
+async fn shared_lru_accept_differs() {
+    let provider = SharedLruProvider::new(NonZeroUsize::new(8).unwrap());
+    let layer = EtagCacheLayer::with_default_predicate(provider).enforce_preconditions(true);
+    accept_differs(layer.layer(app(negotiated))).await;
+}
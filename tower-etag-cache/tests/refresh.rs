@@ -0,0 +1,54 @@
+use bytes::Bytes;
+use http::{header::RANGE, Request, Response, StatusCode};
+use http_body_util::Full;
+use std::{num::NonZeroUsize, time::Duration};
+use tower_etag_cache::{
+    shared_lru_provider::SharedLruProvider, tokio_refresh_spawner::TokioRefreshSpawner,
+    EtagCacheLayer,
+};
+use tower_layer::Layer;
+
+mod common;
+
+use common::{app, etag, req, send};
+
+/// Serves `hello world`, or only its first 5 bytes to `Range` requests
+fn ranged(req: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+    match req.headers().contains_key(RANGE) {
+        true => Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("content-range", "bytes 0-4/11")
+            .body(Full::from("hello"))
+            .unwrap(),
+        false => Response::new(Full::from("hello world")),
+    }
+}
+
+#[tokio::test]
+async fn refresh_of_range_request_saves_full_etag() {
+    let provider =
+        SharedLruProvider::new(NonZeroUsize::new(8).unwrap()).refresh_after(Some(Duration::ZERO));
+    let mut svc = EtagCacheLayer::with_default_predicate(provider)
+        .refresh_with(TokioRefreshSpawner)
+        .layer(app(ranged));
+
+    let (parts, _) = send(&mut svc, req("GET", "/", &[])).await;
+    let full = etag(&parts).to_owned();
+
+    // stale hit, refreshed in the background
+    let (parts, _) = send(
+        &mut svc,
+        req(
+            "GET",
+            "/",
+            &[("if-none-match", &full), ("range", "bytes=0-4")],
+        ),
+    )
+    .await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // the refresh fetched the full body, whose ETag did not change
+    let (parts, _) = send(&mut svc, req("GET", "/", &[("if-none-match", &full)])).await;
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+}
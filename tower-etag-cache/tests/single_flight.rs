@@ -0,0 +1,84 @@
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tower_etag_cache::{
+    base64_blake3_body_etag::Base64Blake3EtagGenerator,
+    const_lru_provider::{ConstLruProvider, ConstLruProviderConfig},
+    EtagCacheLayer, EtagGenerator,
+};
+use tower_layer::Layer;
+
+mod common;
+
+use common::{app, call, req, send};
+
+fn full_responses(stream_threshold: Option<u64>) -> ConstLruProviderConfig {
+    ConstLruProviderConfig {
+        full_response_headers: Some(ConstLruProviderConfig::DEFAULT_FULL_RESPONSE_HEADERS),
+        stream_threshold,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn waiters_are_served_the_saved_response() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn counted(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        Response::new(Full::from("hello"))
+    }
+
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, full_responses(None));
+    let mut svc = EtagCacheLayer::with_default_predicate(provider)
+        .single_flight(true)
+        .layer(app(counted));
+    let mut waiter_svc = svc.clone();
+
+    let ((_, leader), (parts, waiter)) = tokio::join!(
+        send(&mut svc, req("GET", "/", &[])),
+        send(&mut waiter_svc, req("GET", "/", &[])),
+    );
+    assert_eq!(leader, "hello");
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(waiter, "hello");
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn streamed_leader_releases_waiters_after_saving_its_etag() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    fn counted(_: &Request<Full<Bytes>>) -> Response<Full<Bytes>> {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        Response::new(Full::from("hello"))
+    }
+
+    let provider = ConstLruProvider::<_, _, 8>::init_with_config(4, full_responses(Some(0)));
+    let mut svc = EtagCacheLayer::with_default_predicate(provider)
+        .single_flight(true)
+        .layer(app(counted));
+    let mut waiter_svc = svc.clone();
+
+    // streamed bodies are not saved, so the waiter can only be served a 304
+    let (parts, ()) = Response::new(()).into_parts();
+    let etag = Base64Blake3EtagGenerator.generate(&parts, b"hello");
+    let etag = etag.to_header_value();
+    let etag = etag.to_str().unwrap();
+
+    // returned before its body has been streamed and its ETag saved
+    let leader = call(&mut svc, req("GET", "/", &[])).await;
+    let ((parts, _), leader) = tokio::join!(
+        send(&mut waiter_svc, req("GET", "/", &[("if-none-match", etag)])),
+        async {
+            // let the waiter reach the provider first
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            leader.into_body().collect().await.unwrap().to_bytes()
+        },
+    );
+    assert_eq!(leader, "hello");
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+}